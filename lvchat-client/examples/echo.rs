@@ -0,0 +1,47 @@
+//! Bot repeating everything said in a channel.
+//!
+//! `cargo run --example echo -- 127.0.0.1 5050 echo #lobby`
+
+use std::env;
+
+use lvchat_client::{Connection, Endpoint, Event};
+
+fn main() -> std::io::Result<()> {
+    let mut args = env::args().skip(1);
+    let host = args.next().unwrap_or_else(|| "127.0.0.1".to_owned());
+    let port = args
+        .next()
+        .and_then(|port| port.parse().ok())
+        .unwrap_or(5050);
+    let nick = args.next().unwrap_or_else(|| "echo".to_owned());
+    let channel = args.next().unwrap_or_else(|| "#lobby".to_owned());
+
+    let (connection, events) = Connection::connect(&Endpoint::Tcp {
+        host,
+        port,
+        tls: None,
+    })?;
+
+    for event in events.iter() {
+        match event {
+            Event::AuthRequested => {
+                connection.authenticate(&nick, None)?;
+                connection.join(&channel)?;
+            }
+
+            Event::Text {
+                channel,
+                from,
+                message,
+            } if from != nick => {
+                connection.send_text(&channel, &format!("{} said: {}", from, message))?;
+            }
+
+            Event::Error(error) => eprintln!("{:?}", error),
+
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
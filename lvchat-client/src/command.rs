@@ -0,0 +1,600 @@
+//! Slash commands typed into the input line, like `/msg <nick> <message>`.
+
+use std::process::exit;
+
+use lvchat_client::Connection;
+use lvchat_core::{message::is_valid_channel_name, UserMessage};
+
+use crate::{message::ACTION_PREFIX, reconnect::Status, scroll::Scrollback, state::State, view};
+
+/// Runs a command with its arguments, which already are as many as it takes.
+///
+/// Returns `false` if the arguments are invalid, so the usage is shown.
+type Run = fn(&State, &[&str]) -> bool;
+
+pub struct Command {
+    pub name: &'static str,
+
+    /// Arguments as shown by `/help`, optional ones in brackets
+    pub args: &'static str,
+    pub help: &'static str,
+
+    /// Arguments required and taken at most, the last taking the rest of the line
+    required: usize,
+    max: usize,
+
+    run: Run,
+}
+
+impl Command {
+    pub fn usage(&self) -> String {
+        if self.args.is_empty() {
+            format!("/{}", self.name)
+        } else {
+            format!("/{} {}", self.name, self.args)
+        }
+    }
+}
+
+impl std::fmt::Debug for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.usage())
+    }
+}
+
+/// Why a line couldn't be run as a command.
+#[derive(Debug)]
+pub enum Error<'a> {
+    Unknown(&'a str),
+    Usage(&'static Command),
+}
+
+pub static COMMANDS: &[Command] = &[
+    Command {
+        name: "help",
+        args: "[command]",
+        help: "Lists the commands, or describes one",
+        required: 0,
+        max: 1,
+        run: help,
+    },
+    Command {
+        name: "nick",
+        args: "<nick>",
+        help: "Changes your nick",
+        required: 1,
+        max: 1,
+        run: nick,
+    },
+    Command {
+        name: "msg",
+        args: "<nick> <message>",
+        help: "Sends a private message",
+        required: 2,
+        max: 2,
+        run: msg,
+    },
+    Command {
+        name: "me",
+        args: "<action>",
+        help: "Describes what you do to the active channel",
+        required: 1,
+        max: 1,
+        run: me,
+    },
+    Command {
+        name: "join",
+        args: "<#channel>",
+        help: "Joins a channel, or switches to it if already joined",
+        required: 1,
+        max: 1,
+        run: join,
+    },
+    Command {
+        name: "part",
+        args: "[#channel] [reason]",
+        help: "Leaves the active or given channel",
+        required: 0,
+        max: 2,
+        run: part,
+    },
+    Command {
+        name: "users",
+        args: "",
+        help: "Lists the users of the active channel",
+        required: 0,
+        max: 0,
+        run: users,
+    },
+    Command {
+        name: "clear",
+        args: "",
+        help: "Removes all messages from the screen",
+        required: 0,
+        max: 0,
+        run: clear,
+    },
+    Command {
+        name: "history",
+        args: "[count]",
+        help: "Loads older messages of the active channel",
+        required: 0,
+        max: 1,
+        run: history,
+    },
+    Command {
+        name: "register",
+        args: "<password>",
+        help: "Registers your nick with a password",
+        required: 1,
+        max: 1,
+        run: register,
+    },
+    Command {
+        name: "oper",
+        args: "<password>",
+        help: "Becomes an operator",
+        required: 1,
+        max: 1,
+        run: oper,
+    },
+    Command {
+        name: "kick",
+        args: "<nick> [reason]",
+        help: "Disconnects a user (operators only)",
+        required: 1,
+        max: 2,
+        run: kick,
+    },
+    Command {
+        name: "ban",
+        args: "<mask> [seconds] [reason]",
+        help: "Bans addresses matching a mask like 10.0.* (operators only)",
+        required: 1,
+        max: 2,
+        run: ban,
+    },
+    Command {
+        name: "mute",
+        args: "<nick> [seconds]",
+        help: "Silences a user, 0 seconds unmutes (operators only)",
+        required: 1,
+        max: 2,
+        run: mute,
+    },
+    Command {
+        name: "quit",
+        args: "[reason]",
+        help: "Leaves the chat",
+        required: 0,
+        max: 1,
+        run: quit,
+    },
+];
+
+/// Finds the command `line` names and splits off its arguments.
+pub fn parse(line: &str) -> Result<(&'static Command, Vec<&str>), Error<'_>> {
+    let line = line.strip_prefix('/').unwrap_or(line);
+    let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+
+    let command = COMMANDS
+        .iter()
+        .find(|command| command.name.eq_ignore_ascii_case(name))
+        .ok_or(Error::Unknown(name))?;
+
+    let mut args = vec![];
+    let mut rest = rest.trim();
+
+    while !rest.is_empty() {
+        if args.len() + 1 == command.max {
+            args.push(rest);
+            break;
+        }
+
+        match rest.split_once(char::is_whitespace) {
+            Some((arg, tail)) => {
+                args.push(arg);
+                rest = tail.trim_start();
+            }
+
+            None => {
+                args.push(rest);
+                break;
+            }
+        }
+    }
+
+    if args.len() < command.required || args.len() > command.max {
+        return Err(Error::Usage(command));
+    }
+
+    Ok((command, args))
+}
+
+/// Runs the command in `line`, telling the user what was wrong with it.
+pub fn run(state: &State, line: &str) {
+    match parse(line) {
+        Ok((command, args)) => {
+            if !(command.run)(state, &args) {
+                notice(state, format!("Usage: {}", command.usage()));
+            }
+        }
+
+        Err(Error::Unknown(name)) => {
+            notice(
+                state,
+                format!("Unknown command /{}, see /help for the list.", name),
+            );
+        }
+
+        Err(Error::Usage(command)) => {
+            notice(state, format!("Usage: {}", command.usage()));
+        }
+    }
+}
+
+fn notice<T: AsRef<str>>(state: &State, text: T) {
+    state.messages.write().push(view::Message::notice(text));
+}
+
+/// Connection while online, telling the user if not.
+fn online(state: &State) -> Option<Connection> {
+    let connection = match *state.status.read() {
+        Status::Online => state.connection(),
+        _ => None,
+    };
+
+    if connection.is_none() {
+        notice(state, "Not connected.");
+    }
+
+    connection
+}
+
+/// Sends `message` if online, telling the user if not.
+fn send(state: &State, message: UserMessage) {
+    if let Some(connection) = online(state) {
+        let _ = connection.send(message);
+    }
+}
+
+/// Sends what the user wrote, or keeps it until back online.
+fn deliver(state: &State, message: UserMessage) {
+    if let (Status::Online, Some(connection)) = (&*state.status.read(), state.connection()) {
+        let _ = connection.send(message);
+
+        return;
+    }
+
+    let mut outbox = state.outbox.write();
+
+    if outbox.is_empty() {
+        notice(state, "Not connected, sending once reconnected.");
+    }
+
+    outbox.push(message);
+}
+
+/// Sends `text` to the active channel.
+pub fn say(state: &State, text: &str) {
+    let channel = match active_channel(state) {
+        Some(channel) => channel,
+        None => return,
+    };
+
+    deliver(
+        state,
+        UserMessage::Text {
+            channel: channel.clone(),
+            message: text.to_string(),
+        },
+    );
+
+    state
+        .messages
+        .write()
+        .push(view::Message::user(&*state.nick.read(), text).in_channel(channel));
+}
+
+/// Active channel, telling the user if there is none.
+fn active_channel(state: &State) -> Option<String> {
+    let channel = state.channel.read().clone();
+
+    if channel.is_none() {
+        notice(state, "Not in a channel.");
+    }
+
+    channel
+}
+
+fn help(state: &State, args: &[&str]) -> bool {
+    match args.first() {
+        Some(name) => {
+            let name = name.trim_start_matches('/');
+
+            match COMMANDS.iter().find(|command| command.name == name) {
+                Some(command) => notice(state, format!("{}: {}", command.usage(), command.help)),
+                None => notice(state, format!("Unknown command /{}", name)),
+            }
+        }
+
+        None => {
+            notice(state, "Commands, // sends a line starting with a slash:");
+
+            for command in COMMANDS {
+                notice(state, format!("  {}: {}", command.usage(), command.help));
+            }
+        }
+    }
+
+    true
+}
+
+fn nick(state: &State, args: &[&str]) -> bool {
+    // the server confirms with a nick change, which updates ours
+    if let Some(connection) = online(state) {
+        let _ = connection.authenticate(args[0], None);
+    }
+
+    true
+}
+
+fn msg(state: &State, args: &[&str]) -> bool {
+    deliver(
+        state,
+        UserMessage::PrivateText {
+            to: args[0].to_string(),
+            message: args[1].to_string(),
+        },
+    );
+
+    state.messages.write().push(view::Message::private(
+        &*state.nick.read(),
+        args[0],
+        args[1],
+    ));
+
+    true
+}
+
+fn me(state: &State, args: &[&str]) -> bool {
+    say(state, &format!("{}{}", ACTION_PREFIX, args[0]));
+
+    true
+}
+
+fn join(state: &State, args: &[&str]) -> bool {
+    let channel = args[0];
+
+    if !is_valid_channel_name(channel) {
+        notice(state, format!("Invalid channel name: {}", channel));
+
+        return true;
+    }
+
+    if state.members.read().contains_key(channel) {
+        *state.channel.write() = Some(channel.to_string());
+    } else if let Some(connection) = online(state) {
+        // switched to once the member list arrives
+        *state.joining.write() = Some(channel.to_string());
+
+        let _ = connection.join(channel);
+    }
+
+    true
+}
+
+fn part(state: &State, args: &[&str]) -> bool {
+    // the server would rejoin the channel when resuming the session
+    let connection = match online(state) {
+        Some(connection) => connection,
+        None => return true,
+    };
+
+    let (channel, reason) = match args.first() {
+        Some(first) if first.starts_with('#') => {
+            (first.to_string(), args.get(1).map(ToString::to_string))
+        }
+
+        // without a channel everything is the reason
+        _ => match active_channel(state) {
+            Some(channel) => (
+                channel,
+                Some(args.join(" ")).filter(|reason| !reason.is_empty()),
+            ),
+            None => return true,
+        },
+    };
+
+    if state.members.write().remove(&channel).is_none() {
+        notice(state, format!("Not in channel {}", channel));
+
+        return true;
+    }
+
+    let _ = connection.part(&channel, reason.as_deref());
+
+    let mut active = state.channel.write();
+
+    if active.as_ref() == Some(&channel) {
+        *active = state.members.read().keys().min().cloned();
+    }
+
+    true
+}
+
+fn users(state: &State, _: &[&str]) -> bool {
+    let text = match state.channel.read().clone() {
+        Some(channel) => {
+            let members = state
+                .members
+                .read()
+                .get(&channel)
+                .cloned()
+                .unwrap_or_default();
+
+            format!("Users in {}: {}", channel, members.join(", "))
+        }
+
+        None => format!("Users online: {}", state.users.read().join(", ")),
+    };
+
+    notice(state, text);
+
+    true
+}
+
+fn clear(state: &State, _: &[&str]) -> bool {
+    state.messages.write().clear();
+    *state.scroll.write() = Scrollback::default();
+
+    true
+}
+
+/// Asks for messages of the active channel older than the oldest one displayed.
+fn history(state: &State, args: &[&str]) -> bool {
+    let limit = match args.first() {
+        Some(limit) => match limit.parse() {
+            Ok(limit) => limit,
+            Err(_) => return false,
+        },
+
+        None => 50,
+    };
+
+    let (channel, connection) = match (active_channel(state), online(state)) {
+        (Some(channel), Some(connection)) => (channel, connection),
+        _ => return true,
+    };
+
+    let before = state
+        .messages
+        .read()
+        .iter()
+        .filter(|message| message.channel.as_ref() == Some(&channel))
+        .filter_map(|message| message.id)
+        .min();
+
+    state.history_pending.write().insert(channel.clone());
+
+    let _ = connection.request_history(&channel, before, limit);
+
+    true
+}
+
+fn register(state: &State, args: &[&str]) -> bool {
+    send(
+        state,
+        UserMessage::Register {
+            nick: state.nick.read().clone(),
+            password: args[0].into(),
+        },
+    );
+
+    true
+}
+
+fn oper(state: &State, args: &[&str]) -> bool {
+    send(
+        state,
+        UserMessage::Oper {
+            password: args[0].into(),
+        },
+    );
+
+    true
+}
+
+fn kick(state: &State, args: &[&str]) -> bool {
+    send(
+        state,
+        UserMessage::Kick {
+            nick: args[0].to_string(),
+            reason: args.get(1).map(ToString::to_string),
+        },
+    );
+
+    true
+}
+
+fn ban(state: &State, args: &[&str]) -> bool {
+    let rest = args.get(1).copied().unwrap_or_default();
+
+    // an optional duration in seconds precedes the reason
+    let (duration, reason) = match rest.split_once(char::is_whitespace) {
+        Some((duration, reason)) if duration.parse::<u64>().is_ok() => {
+            (duration.parse().ok(), Some(reason.trim()))
+        }
+        _ if rest.parse::<u64>().is_ok() => (rest.parse().ok(), None),
+        _ => (None, Some(rest)),
+    };
+
+    send(
+        state,
+        UserMessage::Ban {
+            mask: args[0].to_string(),
+            duration,
+            reason: reason
+                .filter(|reason| !reason.is_empty())
+                .map(ToString::to_string),
+        },
+    );
+
+    true
+}
+
+fn mute(state: &State, args: &[&str]) -> bool {
+    let duration = match args.get(1) {
+        Some(duration) => match duration.parse() {
+            Ok(duration) => Some(duration),
+            Err(_) => return false,
+        },
+
+        None => None,
+    };
+
+    send(
+        state,
+        UserMessage::Mute {
+            nick: args[0].to_string(),
+            duration,
+        },
+    );
+
+    true
+}
+
+fn quit(state: &State, args: &[&str]) -> bool {
+    if let Some(connection) = state.connection() {
+        let _ = connection.leave(args.first().copied());
+    }
+
+    quit!();
+}
+
+#[test]
+fn parse_arguments() {
+    let (command, args) = parse("/msg bob hello  there").unwrap();
+    assert_eq!(command.name, "msg");
+    assert_eq!(args, ["bob", "hello  there"]);
+
+    let (command, args) = parse("/QUIT").unwrap();
+    assert_eq!(command.name, "quit");
+    assert!(args.is_empty());
+
+    assert_eq!(parse("/quit see you").unwrap().1, ["see you"]);
+    assert_eq!(
+        parse("/part #rust gone  fishing").unwrap().1,
+        ["#rust", "gone  fishing"]
+    );
+
+    let usage = |line| match parse(line) {
+        Err(Error::Usage(command)) => command.name,
+        _ => panic!("{} is valid", line),
+    };
+    assert_eq!(usage("/nick"), "nick");
+    assert_eq!(usage("/msg bob"), "msg");
+    assert_eq!(usage("/clear all"), "clear");
+
+    assert!(matches!(parse("/nikc bob"), Err(Error::Unknown("nikc"))));
+}
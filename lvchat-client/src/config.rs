@@ -2,6 +2,9 @@ use std::path::PathBuf;
 
 use structopt::StructOpt;
 
+use lvchat_client::{Endpoint, Trust};
+use lvchat_core::message::Password;
+
 #[derive(Debug, StructOpt)]
 pub struct Config {
     #[structopt(short, long)]
@@ -13,33 +16,60 @@ pub struct Config {
     #[structopt(short, long)]
     pub quiet: bool,
 
-    #[structopt(short, long)]
+    #[structopt(short, long, default_value = "127.0.0.1")]
     pub host: String,
 
     #[structopt(short, long, default_value = "5050")]
     pub port: u16,
 
+    /// Unix domain socket of a server on this host, used instead of host and port
+    #[structopt(long = "socket")]
+    pub socket_path: Option<PathBuf>,
+
     #[structopt(short, long)]
     pub nick: String,
 
+    /// Password of the account registered for the nick
+    #[structopt(long, parse(from_str))]
+    pub password: Option<Password>,
+
+    /// PEM encoded CA certificate the server's certificate must be issued by. Enables TLS
+    #[structopt(long = "ca")]
+    pub ca_path: Option<PathBuf>,
+
+    /// SHA-256 fingerprint of the server's certificate, trusted without a CA. Enables TLS
+    #[structopt(long)]
+    pub fingerprint: Option<String>,
+
+    /// Channel to join once authenticated
+    #[structopt(short, long, default_value = "#lobby")]
+    pub channel: String,
+
     #[structopt(long = "logs")]
     pub logs_path: Option<PathBuf>,
 }
 
 impl Config {
     pub fn new() -> Self {
-        if cfg!(debug_assertions) {
-            Config {
-                verbose: true,
-                debug: true,
-                quiet: false,
-                host: format!("127.0.0.1"),
-                nick: format!("avonarret"),
-                port: 5050,
-                logs_path: None, //Some(PathBuf::from("logs")),
-            }
-        } else {
-            <Self as StructOpt>::from_args()
+        <Self as StructOpt>::from_args()
+    }
+
+    /// Server to connect to. A pinned fingerprint takes precedence over a CA.
+    pub fn endpoint(&self) -> Endpoint {
+        if let Some(ref path) = self.socket_path {
+            return Endpoint::Unix(path.clone());
+        }
+
+        let tls = match (&self.fingerprint, &self.ca_path) {
+            (Some(fingerprint), _) => Some(Trust::Fingerprint(fingerprint.clone())),
+            (None, Some(ca)) => Some(Trust::Ca(ca.clone())),
+            (None, None) => None,
+        };
+
+        Endpoint::Tcp {
+            host: self.host.clone(),
+            port: self.port,
+            tls,
         }
     }
 }
@@ -0,0 +1,520 @@
+//! Connection to a server, speaking the protocol on behalf of the terminal client as well as
+//! bots and scripts.
+
+use std::{
+    fmt,
+    io::{self, ErrorKind, Read, Write},
+    net::TcpStream,
+    path::PathBuf,
+    sync::Arc,
+    thread,
+};
+
+use flume::Receiver;
+use parking_lot::Mutex;
+
+use lvchat_core::{
+    ErrorMessage, FrameDecoder, Hello, Message, RecordedMessage, ServerMessage, UserMessage,
+};
+
+use crate::tls::{Stream, Trust};
+
+const SOFTWARE: &str = concat!("lvchat-client ", env!("CARGO_PKG_VERSION"));
+
+/// Where the server is reached.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    Tcp {
+        host: String,
+        port: u16,
+
+        /// Connects over TLS if given.
+        tls: Option<Trust>,
+    },
+
+    /// Unix domain socket of a server on this host.
+    Unix(PathBuf),
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Endpoint::Tcp { host, port, .. } => write!(f, "{}:{}", host, port),
+            Endpoint::Unix(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// What the server tells the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Hello(Hello),
+
+    /// Server waits for `Connection::authenticate` or `Connection::resume`.
+    AuthRequested,
+
+    /// Token to `Connection::resume` the session with after reconnecting.
+    Session(String),
+
+    /// Session was taken over, rejoining `channels` as `nick`.
+    Resumed {
+        nick: String,
+        channels: Vec<String>,
+    },
+
+    Notice(String),
+
+    /// Sent after authentication, in place of the welcome notice.
+    MessageOfTheDay(Option<String>),
+
+    /// Everyone else online.
+    UserList(Vec<String>),
+
+    MemberList {
+        channel: String,
+        users: Vec<String>,
+    },
+
+    /// Recorded messages of a channel, oldest first.
+    History {
+        channel: String,
+        messages: Vec<RecordedMessage>,
+    },
+
+    Connected {
+        nick: String,
+    },
+    NickChanged {
+        from: String,
+        to: String,
+    },
+    Left {
+        nick: String,
+        message: Option<String>,
+    },
+
+    Joined {
+        channel: String,
+        nick: String,
+    },
+    Parted {
+        channel: String,
+        nick: String,
+        message: Option<String>,
+    },
+
+    Text {
+        channel: String,
+        from: String,
+        message: String,
+    },
+
+    /// Text sent to a channel as the server recorded it, including the client's own.
+    Recorded {
+        channel: String,
+        message: RecordedMessage,
+    },
+    PrivateText {
+        from: String,
+        to: String,
+        message: String,
+    },
+
+    Kicked {
+        nick: String,
+        by: String,
+        reason: Option<String>,
+    },
+    Banned {
+        mask: String,
+        by: String,
+        duration: Option<u64>,
+        reason: Option<String>,
+    },
+
+    /// A `duration` of 0 means the mute was lifted.
+    Muted {
+        nick: String,
+        by: String,
+        duration: Option<u64>,
+    },
+
+    /// Server is going down and closes the connection right after.
+    Shutdown(Option<String>),
+
+    Error(ErrorMessage),
+
+    /// Connection was closed, always the last event.
+    Disconnected,
+}
+
+impl Event {
+    /// Translates a message from the server, `None` for those meaningless to a client.
+    pub fn from_message(message: Message) -> Option<Self> {
+        let event = match message {
+            Message::User(_) => return None,
+
+            Message::Server(message) => match message {
+                ServerMessage::Hello(hello) => Event::Hello(hello),
+                ServerMessage::Auth => Event::AuthRequested,
+                ServerMessage::Session { token } => Event::Session(token),
+                ServerMessage::Resumed { nick, channels } => Event::Resumed { nick, channels },
+                ServerMessage::Notice { message } => Event::Notice(message),
+                ServerMessage::MessageOfTheDay { message } => Event::MessageOfTheDay(message),
+                ServerMessage::UserList { users } => Event::UserList(users),
+                ServerMessage::MemberList { channel, users } => {
+                    Event::MemberList { channel, users }
+                }
+                ServerMessage::HistoryPage { channel, messages } => {
+                    Event::History { channel, messages }
+                }
+                ServerMessage::Recorded { channel, message } => {
+                    Event::Recorded { channel, message }
+                }
+                ServerMessage::Shutdown { message } => Event::Shutdown(message),
+                ServerMessage::Refer { user, message } => return Self::from_refer(user, message),
+                // only sent to revisions without message ids
+                ServerMessage::History { .. } => return None,
+                ServerMessage::Ping { .. } | ServerMessage::Pong { .. } => return None,
+            },
+
+            Message::Error(error) => Event::Error(error),
+        };
+
+        Some(event)
+    }
+
+    /// Translates what `user` did.
+    fn from_refer(user: String, message: UserMessage) -> Option<Self> {
+        let event = match message {
+            UserMessage::Auth { nick, .. } if nick == user => Event::Connected { nick },
+            UserMessage::Auth { nick, .. } => Event::NickChanged {
+                from: user,
+                to: nick,
+            },
+            UserMessage::Leave { message } => Event::Left {
+                nick: user,
+                message,
+            },
+            UserMessage::Join { channel } => Event::Joined {
+                channel,
+                nick: user,
+            },
+            UserMessage::Part { channel, message } => Event::Parted {
+                channel,
+                nick: user,
+                message,
+            },
+            UserMessage::Text { channel, message } => Event::Text {
+                channel,
+                from: user,
+                message,
+            },
+            UserMessage::PrivateText { to, message } => Event::PrivateText {
+                from: user,
+                to,
+                message,
+            },
+            UserMessage::Kick { nick, reason } => Event::Kicked {
+                nick,
+                by: user,
+                reason,
+            },
+            UserMessage::Ban {
+                mask,
+                duration,
+                reason,
+            } => Event::Banned {
+                mask,
+                by: user,
+                duration,
+                reason,
+            },
+            UserMessage::Mute { nick, duration } => Event::Muted {
+                nick,
+                by: user,
+                duration,
+            },
+
+            UserMessage::Hello(_)
+            | UserMessage::RequestUserList
+            | UserMessage::RequestMemberList { .. }
+            | UserMessage::RequestHistory { .. }
+            | UserMessage::RequestHistoryPage { .. }
+            | UserMessage::Ping { .. }
+            | UserMessage::Pong { .. }
+            | UserMessage::Resume { .. }
+            | UserMessage::Register { .. }
+            | UserMessage::Oper { .. }
+            | UserMessage::Voice { .. } => return None,
+        };
+
+        Some(event)
+    }
+}
+
+/// Sending half of a connection to a server, cheap to clone and share between threads.
+///
+/// Incoming messages are read on a background thread, which answers pings and hands out
+/// everything else through the receiver returned by `connect`.
+#[derive(Debug, Clone)]
+pub struct Connection {
+    stream: Arc<Mutex<Stream>>,
+}
+
+impl Connection {
+    /// Connects to `endpoint` and greets the server, which answers with `Event::AuthRequested`.
+    ///
+    /// Events arrive in the order they were received, ending with `Event::Disconnected`.
+    pub fn connect(endpoint: &Endpoint) -> io::Result<(Self, Receiver<Event>)> {
+        let mut stream = match endpoint {
+            Endpoint::Tcp { host, port, tls } => Stream::new(
+                TcpStream::connect((host.as_str(), *port))?,
+                host,
+                tls.as_ref(),
+            )?,
+
+            Endpoint::Unix(path) => connect_local(path)?,
+        };
+
+        Message::send(&mut stream, UserMessage::Hello(Hello::new(SOFTWARE)))?;
+
+        let reader = stream.try_clone()?;
+        let stream = Arc::new(Mutex::new(stream));
+        let events = read(reader, stream.clone());
+
+        Ok((Connection { stream }, events))
+    }
+
+    pub fn send<M: Into<Message>>(&self, message: M) -> io::Result<()> {
+        send(&self.stream, message.into())
+    }
+
+    /// Claims `nick`, which requires its password if it belongs to a registered account.
+    pub fn authenticate(&self, nick: &str, password: Option<&str>) -> io::Result<()> {
+        self.send(UserMessage::Auth {
+            nick: nick.to_owned(),
+            password: password.map(Into::into),
+        })
+    }
+
+    /// Authenticates by taking over the session of a previous connection.
+    pub fn resume(&self, token: &str) -> io::Result<()> {
+        self.send(UserMessage::Resume {
+            token: token.to_owned(),
+        })
+    }
+
+    pub fn join(&self, channel: &str) -> io::Result<()> {
+        self.send(UserMessage::Join {
+            channel: channel.to_owned(),
+        })
+    }
+
+    pub fn part(&self, channel: &str, message: Option<&str>) -> io::Result<()> {
+        self.send(UserMessage::Part {
+            channel: channel.to_owned(),
+            message: message.map(ToOwned::to_owned),
+        })
+    }
+
+    pub fn send_text(&self, channel: &str, message: &str) -> io::Result<()> {
+        self.send(UserMessage::Text {
+            channel: channel.to_owned(),
+            message: message.to_owned(),
+        })
+    }
+
+    pub fn send_private(&self, to: &str, message: &str) -> io::Result<()> {
+        self.send(UserMessage::PrivateText {
+            to: to.to_owned(),
+            message: message.to_owned(),
+        })
+    }
+
+    /// Asks for up to `limit` messages of `channel` recorded before the one with the id `before`,
+    /// or the newest ones if `None`.
+    pub fn request_history(
+        &self,
+        channel: &str,
+        before: Option<u64>,
+        limit: u32,
+    ) -> io::Result<()> {
+        self.send(UserMessage::RequestHistoryPage {
+            channel: channel.to_owned(),
+            before,
+            limit,
+        })
+    }
+
+    /// Says goodbye and closes the connection.
+    pub fn leave(&self, message: Option<&str>) -> io::Result<()> {
+        self.send(UserMessage::Leave {
+            message: message.map(ToOwned::to_owned),
+        })?;
+
+        self.stream.lock().shutdown(std::net::Shutdown::Both)
+    }
+}
+
+#[cfg(unix)]
+fn connect_local(path: &std::path::Path) -> io::Result<Stream> {
+    std::os::unix::net::UnixStream::connect(path).map(Stream::Unix)
+}
+
+#[cfg(not(unix))]
+fn connect_local(_path: &std::path::Path) -> io::Result<Stream> {
+    Err(io::Error::new(
+        ErrorKind::Unsupported,
+        "Unix domain sockets aren't supported on this platform",
+    ))
+}
+
+/// Writes `message` to the stream, blocking other senders until all of it is written.
+fn send(stream: &Mutex<Stream>, message: Message) -> io::Result<()> {
+    let frame = message.to_frame()?;
+    let mut stream = stream.lock();
+
+    stream.write_all(&frame)?;
+    stream.flush()
+}
+
+/// Reads from `reader` until the stream closes, answering pings through `writer`.
+fn read(mut reader: Stream, writer: Arc<Mutex<Stream>>) -> Receiver<Event> {
+    let (tx, rx) = flume::unbounded();
+
+    thread::spawn(move || {
+        let mut decoder = FrameDecoder::default();
+        let mut buffer = [0u8; 1024];
+
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(size) => decoder.extend(&buffer[..size]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    log::debug!("Connection broke: {}", e);
+                    break;
+                }
+            }
+
+            loop {
+                let frame = match decoder.decode() {
+                    Ok(Some(frame)) => frame,
+                    Ok(None) => break,
+                    Err(e) => {
+                        log::warn!("Server sent an invalid frame: {}", e);
+
+                        let _ = tx.send(Event::Disconnected);
+                        return;
+                    }
+                };
+
+                match Message::from_bytes(&frame) {
+                    Some(Message::Server(ServerMessage::Ping { token })) => {
+                        let _ = send(&writer, UserMessage::Pong { token }.into());
+                    }
+
+                    Some(message) => {
+                        if let Some(event) = Event::from_message(message) {
+                            let _ = tx.send(event);
+                        }
+                    }
+
+                    None => log::warn!("Server sent an undecodable message"),
+                }
+            }
+        }
+
+        let _ = tx.send(Event::Disconnected);
+    });
+
+    rx
+}
+
+#[test]
+fn translate_server_messages() {
+    let refer = |user: &str, message| {
+        Event::from_message(
+            ServerMessage::Refer {
+                user: user.to_owned(),
+                message,
+            }
+            .into(),
+        )
+    };
+
+    assert_eq!(
+        refer(
+            "alice",
+            UserMessage::Auth {
+                nick: "alice".to_owned(),
+                password: None,
+            }
+        ),
+        Some(Event::Connected {
+            nick: "alice".to_owned()
+        })
+    );
+    assert_eq!(
+        refer(
+            "alice",
+            UserMessage::Auth {
+                nick: "bob".to_owned(),
+                password: None,
+            }
+        ),
+        Some(Event::NickChanged {
+            from: "alice".to_owned(),
+            to: "bob".to_owned()
+        })
+    );
+    assert_eq!(
+        refer(
+            "alice",
+            UserMessage::Text {
+                channel: "#lobby".to_owned(),
+                message: "hi".to_owned(),
+            }
+        ),
+        Some(Event::Text {
+            channel: "#lobby".to_owned(),
+            from: "alice".to_owned(),
+            message: "hi".to_owned()
+        })
+    );
+
+    let recorded = RecordedMessage {
+        id: 3,
+        timestamp: 1000,
+        user: "alice".to_owned(),
+        message: "hi".to_owned(),
+    };
+
+    assert_eq!(
+        Event::from_message(
+            ServerMessage::Recorded {
+                channel: "#lobby".to_owned(),
+                message: recorded.clone(),
+            }
+            .into()
+        ),
+        Some(Event::Recorded {
+            channel: "#lobby".to_owned(),
+            message: recorded
+        })
+    );
+
+    assert_eq!(
+        Event::from_message(ServerMessage::Auth.into()),
+        Some(Event::AuthRequested)
+    );
+    assert_eq!(
+        Event::from_message(ServerMessage::Ping { token: 1 }.into()),
+        None
+    );
+    assert_eq!(
+        Event::from_message(ErrorMessage::InvalidSession.into()),
+        Some(Event::Error(ErrorMessage::InvalidSession))
+    );
+}
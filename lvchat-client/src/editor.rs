@@ -0,0 +1,199 @@
+//! Single line editor behind the input line.
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Change to the edited line, as bound to a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edit {
+    Insert(char),
+
+    /// Removes the grapheme before the cursor.
+    DeleteBack,
+    /// Removes the grapheme under the cursor.
+    DeleteForward,
+    DeleteWordBack,
+    DeleteWordForward,
+    DeleteToStart,
+    DeleteToEnd,
+
+    Left,
+    Right,
+    WordLeft,
+    WordRight,
+    Home,
+    End,
+}
+
+/// Text being typed, with the cursor always on a grapheme boundary.
+#[derive(Debug, Clone, Default)]
+pub struct LineEditor {
+    line: String,
+
+    /// Byte offset into `line`
+    cursor: usize,
+}
+
+impl LineEditor {
+    pub fn apply(&mut self, edit: Edit) {
+        match edit {
+            Edit::Insert(c) => {
+                self.line.insert(self.cursor, c);
+                self.cursor += c.len_utf8();
+
+                // a combining character may have merged into the grapheme before it
+                self.cursor = self.next_boundary(self.prev_boundary(self.cursor));
+            }
+
+            Edit::DeleteBack => self.delete_to(self.prev_boundary(self.cursor)),
+            Edit::DeleteForward => self.delete_to(self.next_boundary(self.cursor)),
+            Edit::DeleteWordBack => self.delete_to(self.prev_word(self.cursor)),
+            Edit::DeleteWordForward => self.delete_to(self.next_word(self.cursor)),
+            Edit::DeleteToStart => self.delete_to(0),
+            Edit::DeleteToEnd => self.delete_to(self.line.len()),
+
+            Edit::Left => self.cursor = self.prev_boundary(self.cursor),
+            Edit::Right => self.cursor = self.next_boundary(self.cursor),
+            Edit::WordLeft => self.cursor = self.prev_word(self.cursor),
+            Edit::WordRight => self.cursor = self.next_word(self.cursor),
+            Edit::Home => self.cursor = 0,
+            Edit::End => self.cursor = self.line.len(),
+        }
+    }
+
+    /// Empties the editor, returning the line.
+    pub fn take(&mut self) -> String {
+        self.cursor = 0;
+
+        std::mem::take(&mut self.line)
+    }
+
+    /// Part of the line fitting into `width` columns around the cursor, and the column of the
+    /// cursor within it.
+    pub fn visible(&self, width: usize) -> (&str, usize) {
+        let mut start = 0;
+        let mut column = self.line[..self.cursor].width();
+
+        // keep one column free for the cursor at the end of the line
+        for (offset, grapheme) in self.line.grapheme_indices(true) {
+            if column < width.max(1) {
+                break;
+            }
+
+            start = offset + grapheme.len();
+            column -= grapheme.width();
+        }
+
+        (&self.line[start..], column)
+    }
+
+    fn delete_to(&mut self, target: usize) {
+        if target < self.cursor {
+            self.line.replace_range(target..self.cursor, "");
+            self.cursor = target;
+        } else {
+            self.line.replace_range(self.cursor..target, "");
+        }
+    }
+
+    fn prev_boundary(&self, from: usize) -> usize {
+        self.line[..from]
+            .grapheme_indices(true)
+            .next_back()
+            .map(|(offset, _)| offset)
+            .unwrap_or_default()
+    }
+
+    fn next_boundary(&self, from: usize) -> usize {
+        self.line[from..]
+            .graphemes(true)
+            .next()
+            .map(|grapheme| from + grapheme.len())
+            .unwrap_or(from)
+    }
+
+    /// Start of the word before `from`, skipping whitespace in between.
+    fn prev_word(&self, from: usize) -> usize {
+        let mut target = from;
+        let mut in_word = false;
+
+        for (offset, grapheme) in self.line[..from].grapheme_indices(true).rev() {
+            let space = grapheme.chars().all(char::is_whitespace);
+
+            if space && in_word {
+                break;
+            }
+
+            in_word |= !space;
+            target = offset;
+        }
+
+        target
+    }
+
+    /// End of the word after `from`, skipping whitespace in between.
+    fn next_word(&self, from: usize) -> usize {
+        let mut target = from;
+        let mut in_word = false;
+
+        for grapheme in self.line[from..].graphemes(true) {
+            let space = grapheme.chars().all(char::is_whitespace);
+
+            if space && in_word {
+                break;
+            }
+
+            in_word |= !space;
+            target += grapheme.len();
+        }
+
+        target
+    }
+}
+
+#[test]
+fn edit_graphemes_and_words() {
+    let mut editor = LineEditor::default();
+
+    for c in "hällo wörld 👋🏽".chars() {
+        editor.apply(Edit::Insert(c));
+    }
+
+    editor.apply(Edit::DeleteBack);
+    assert_eq!(editor.visible(80).0, "hällo wörld ");
+
+    editor.apply(Edit::DeleteWordBack);
+    assert_eq!(editor.visible(80).0, "hällo ");
+
+    editor.apply(Edit::Home);
+    editor.apply(Edit::Right);
+    editor.apply(Edit::Right);
+    editor.apply(Edit::DeleteBack);
+    editor.apply(Edit::Insert('e'));
+    assert_eq!(editor.visible(80).0, "hello ");
+
+    editor.apply(Edit::DeleteWordForward);
+    assert_eq!(editor.visible(80).0, "he ");
+
+    // a combining accent stays with its base letter
+    editor.apply(Edit::End);
+    editor.apply(Edit::Insert('e'));
+    editor.apply(Edit::Insert('\u{301}'));
+    editor.apply(Edit::Left);
+    assert_eq!(editor.visible(80), ("he e\u{301}", 3));
+
+    assert_eq!(editor.take(), "he e\u{301}");
+    assert_eq!(editor.visible(80), ("", 0));
+}
+
+#[test]
+fn scroll_to_cursor() {
+    let mut editor = LineEditor::default();
+
+    for c in "日本語です".chars() {
+        editor.apply(Edit::Insert(c));
+    }
+
+    assert_eq!(editor.visible(80), ("日本語です", 10));
+    assert_eq!(editor.visible(6), ("です", 4));
+}
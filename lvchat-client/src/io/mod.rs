@@ -1,2 +1 @@
 pub mod user;
-pub mod server;
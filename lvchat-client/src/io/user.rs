@@ -1,78 +1,95 @@
-use std::{
-    io::Read,
-    ops::{Deref, DerefMut},
-    thread::spawn,
-};
+use std::thread::spawn;
 
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers, MouseEvent};
 use flume::Receiver;
 
-use crate::event::Event;
+use crate::editor::Edit;
 
-#[derive(Debug)]
-pub enum State {
-    Edit(String),
-    Sent(String),
-}
+/// What the user asked for at the terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    Edit(Edit),
 
-impl State {
-    pub fn is_sent(&self) -> bool {
-        matches!(self, Self::Sent(_))
-    }
-}
+    /// Sends the edited line.
+    Submit,
 
-impl Deref for State {
-    type Target = String;
+    /// Ctrl+C or Ctrl+D, the terminal doesn't raise signals in raw mode.
+    Quit,
 
-    fn deref(&self) -> &Self::Target {
-        match self {
-            State::Edit(data) | State::Sent(data) => data,
-        }
-    }
+    /// Scrolls the messages by a page or a mouse wheel step.
+    PageUp,
+    PageDown,
+    ScrollUp,
+    ScrollDown,
+
+    /// Terminal was resized.
+    Redraw,
 }
 
-impl DerefMut for State {
-    fn deref_mut(&mut self) -> &mut <Self as Deref>::Target {
-        match self {
-            State::Edit(ref mut data) | State::Sent(ref mut data) => data,
-        }
+impl Input {
+    /// Binding of `key`, `None` for keys without one.
+    fn from_key(key: KeyEvent) -> Option<Self> {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+
+        let edit = match key.code {
+            KeyCode::Enter => return Some(Input::Submit),
+            KeyCode::PageUp => return Some(Input::PageUp),
+            KeyCode::PageDown => return Some(Input::PageDown),
+
+            KeyCode::Char('c') | KeyCode::Char('d') if ctrl => return Some(Input::Quit),
+            KeyCode::Char('w') if ctrl => Edit::DeleteWordBack,
+            KeyCode::Char('u') if ctrl => Edit::DeleteToStart,
+            KeyCode::Char('k') if ctrl => Edit::DeleteToEnd,
+            KeyCode::Char('a') if ctrl => Edit::Home,
+            KeyCode::Char('e') if ctrl => Edit::End,
+            KeyCode::Char('b') if alt => Edit::WordLeft,
+            KeyCode::Char('f') if alt => Edit::WordRight,
+            KeyCode::Char('d') if alt => Edit::DeleteWordForward,
+            KeyCode::Char(c) if !ctrl && !alt => Edit::Insert(c),
+
+            KeyCode::Backspace if ctrl || alt => Edit::DeleteWordBack,
+            KeyCode::Backspace => Edit::DeleteBack,
+            KeyCode::Delete if ctrl || alt => Edit::DeleteWordForward,
+            KeyCode::Delete => Edit::DeleteForward,
+
+            KeyCode::Left if ctrl || alt => Edit::WordLeft,
+            KeyCode::Left => Edit::Left,
+            KeyCode::Right if ctrl || alt => Edit::WordRight,
+            KeyCode::Right => Edit::Right,
+            KeyCode::Home => Edit::Home,
+            KeyCode::End => Edit::End,
+
+            _ => return None,
+        };
+
+        Some(Input::Edit(edit))
     }
 }
 
-pub fn capture() -> Receiver<Event> {
+/// Reads terminal events, which requires raw mode to be enabled.
+pub fn capture() -> Receiver<Input> {
     let (tx, rx) = flume::unbounded();
 
-    spawn(move || {
-        let mut input = String::new();
+    spawn(move || loop {
+        let input = match event::read() {
+            Ok(Event::Key(key)) => Input::from_key(key),
+            Ok(Event::Resize(..)) => Some(Input::Redraw),
+            Ok(Event::Mouse(MouseEvent::ScrollUp(..))) => Some(Input::ScrollUp),
+            Ok(Event::Mouse(MouseEvent::ScrollDown(..))) => Some(Input::ScrollDown),
+            Ok(Event::Mouse(_)) => None,
 
-        loop {
-            match std::io::stdin().bytes().take(1).last().unwrap() {
-                Ok(any) => match any {
-                    b'\n' => {
-                        let _ = tx.send(State::Sent(input.clone()).into());
+            Err(e) => {
+                log::error!("Failed to read from terminal: {}", e);
 
-                        input.clear();
-
-                        continue;
-                    }
-
-                    b'\r' => {}
-
-                    // backspace
-                    8 => {
-                        input.pop();
-                    }
-
-                    other if !other.is_ascii_control() => {
-                        input.push(other as char);
-                    }
-
-                    _ => (),
-                },
-
-                _ => (),
+                return;
             }
+        };
 
-            let _ = tx.send(State::Edit(input.clone()).into());
+        if let Some(input) = input {
+            if tx.send(input).is_err() {
+                return;
+            }
         }
     });
 
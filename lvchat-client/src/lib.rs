@@ -0,0 +1,7 @@
+pub use crate::{
+    connection::{Connection, Endpoint, Event},
+    tls::Trust,
+};
+
+pub mod connection;
+pub mod tls;
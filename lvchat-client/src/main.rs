@@ -1,58 +1,154 @@
-use std::{net::TcpStream, process::exit};
+use std::{
+    process::exit,
+    thread,
+    time::{Duration, Instant},
+};
+
+use chrono::TimeZone;
+use flume::{Receiver, RecvTimeoutError, Sender};
 
-use lvchat_core::{Message, UserMessage};
+use lvchat_client::Event;
+use lvchat_core::{message::Password, ErrorMessage, RecordedMessage};
 
 use crate::{
-    config::Config, event::Event, io::user::State as UserInputState, state::State, view::View,
+    config::Config,
+    io::user::Input,
+    reconnect::{Attempt, Reconnect, Status},
+    state::State,
+    view::View,
 };
 
+/// Restores the terminal and exits, printing the reason like `eprintln!`.
+macro_rules! quit {
+    () => {{
+        view::restore_terminal();
+        exit(0)
+    }};
+    ($($arg:tt)*) => {{
+        view::restore_terminal();
+        eprintln!($($arg)*);
+        exit(0)
+    }};
+}
+
+mod command;
 mod config;
-mod event;
+mod editor;
 mod io;
 mod message;
+mod reconnect;
+mod scroll;
 mod state;
 mod view;
 
+/// How often the status bar counts down while waiting to reconnect.
+const COUNTDOWN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What the main loop woke up for, other than a timeout.
+///
+/// Everything arrives through a single channel, as flume's `Selector` can't wait with a timeout.
+enum Wake {
+    Input(Input),
+    Server(Event),
+    Attempt(Attempt),
+}
+
 fn main() {
     let config = Config::new();
 
     init_logger(&config);
 
-    let stream = connect(&config);
+    let mut reconnect = Reconnect::new(config.endpoint());
+    let (wake, woken) = flume::unbounded();
 
-    let state = State::new(config, stream);
+    let state = State::new(config);
     let mut view = View::default();
+    let mut rendered_at = Instant::now();
 
-    let events = [
-        io::user::capture(),
-        io::server::capture(state.stream.clone()),
-    ];
+    forward(io::user::capture(), &wake, Wake::Input);
 
     view.clear();
+    view.render(&state);
 
-    'main: loop {
-        // balance load. Rendering at least each 750ms(?) or so to make responses smoother to view
-        for event_rx in &events {
-            while let Ok(event) = event_rx.try_recv() {
-                match event {
-                    Event::UserInput(input) => {
-                        handle_user_input(&state, input);
-                    }
-                    Event::Message(message) => {
-                        handle_server_message(&state, message);
-                    }
-                    Event::Disconnected => {
-                        break 'main;
-                    }
+    loop {
+        reconnect.poll(&state.status, &wake, Wake::Attempt);
+
+        // redraw the countdown while offline
+        let countdown = match *state.status.read() {
+            Status::Online => None,
+            _ => Some(rendered_at + COUNTDOWN_INTERVAL),
+        };
+
+        let deadline = match (countdown, reconnect.next_deadline()) {
+            (Some(countdown), Some(retry)) => Some(countdown.min(retry)),
+            (countdown, retry) => countdown.or(retry),
+        };
+
+        let woken = match deadline {
+            Some(deadline) => woken.recv_deadline(deadline),
+            None => woken.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        match woken {
+            Ok(Wake::Input(input)) => handle_user_input(&state, input),
+
+            Ok(Wake::Server(Event::Disconnected)) => {
+                let online = *state.status.read() == Status::Online;
+
+                *state.connection.write() = None;
+
+                reconnect.lost(online, &state.status);
+
+                if online {
+                    state
+                        .messages
+                        .write()
+                        .push(view::Message::notice("Connection lost, reconnecting."));
+                }
+            }
+
+            Ok(Wake::Server(event)) => handle_server_event(&state, event),
+
+            Ok(Wake::Attempt(attempt)) => {
+                if let Some((connection, events)) = reconnect.finish(attempt, &state.status) {
+                    *state.connection.write() = Some(connection);
+
+                    forward(events, &wake, Wake::Server);
+                }
+            }
+
+            // the countdown is due, or the next attempt
+            Err(_) => {
+                if *state.status.read() != Status::Online
+                    && rendered_at.elapsed() >= COUNTDOWN_INTERVAL
+                {
+                    view.render(&state);
+                    rendered_at = Instant::now();
                 }
 
-                view.update(&state);
-                view.render(&state);
+                continue;
             }
         }
+
+        view.update(&state);
+        view.render(&state);
+        rendered_at = Instant::now();
     }
 }
 
+/// Passes everything received from `receiver` on to the main loop.
+fn forward<T: Send + 'static>(receiver: Receiver<T>, wake: &Sender<Wake>, wrap: fn(T) -> Wake) {
+    let wake = wake.clone();
+
+    thread::spawn(move || {
+        for item in receiver.iter() {
+            if wake.send(wrap(item)).is_err() {
+                return;
+            }
+        }
+    });
+}
+
 fn init_logger(config: &Config) {
     let logger = if config.debug {
         flexi_logger::Logger::with_str("lvchat_core=debug, lvchat_client=debug")
@@ -79,148 +175,557 @@ fn init_logger(config: &Config) {
     logger.start().unwrap();
 }
 
-fn connect(config: &Config) -> TcpStream {
-    println!(
-        "Trying to connect to remote host ({}:{})",
-        config.host, config.port
-    );
+fn handle_user_input(state: &State, input: Input) {
+    match input {
+        Input::Edit(edit) => state.input.write().apply(edit),
 
-    match TcpStream::connect((config.host.as_str(), config.port)) {
-        Ok(stream) => {
-            println!("Connected.");
+        Input::Submit => {
+            let line = state.input.write().take();
 
-            stream
+            handle_line(state, line.trim());
         }
 
-        Err(e) => {
-            eprintln!("Failed to connect to remote host: {}", e);
+        Input::Quit => {
+            if let Some(connection) = state.connection() {
+                let _ = connection.leave(None);
+            }
 
-            exit(0);
+            quit!();
         }
+
+        Input::PageUp => state.scroll.write().page_up(),
+        Input::PageDown => state.scroll.write().page_down(),
+        Input::ScrollUp => state.scroll.write().scroll_up(scroll::WHEEL_LINES),
+        Input::ScrollDown => state.scroll.write().scroll_down(scroll::WHEEL_LINES),
+
+        Input::Redraw => {}
     }
 }
 
-fn handle_user_input(state: &State, input_state: UserInputState) {
-    if input_state.is_sent() {
-        state.input.write().clear();
+fn handle_line(state: &State, line: &str) {
+    if line.is_empty() {
+        return;
+    }
 
-        match (*input_state).as_str() {
-            "/quit" => {
-                let _ = Message::send(
-                    &mut *state.stream.lock(),
-                    UserMessage::Leave { message: None },
-                );
+    // a doubled slash sends the line with a single one
+    match line.strip_prefix('/') {
+        Some(text) if text.starts_with('/') => command::say(state, text),
+        Some(_) => command::run(state, line),
+        None => command::say(state, line),
+    }
+}
 
-                let _ = state.stream.lock().shutdown(std::net::Shutdown::Both);
+/// Merges recorded messages into the message list, skipping those already displayed.
+fn insert_history(state: &State, channel: String, entries: Vec<RecordedMessage>) {
+    let pending = state.history_pending.write().remove(&channel);
 
-                std::process::exit(0);
-            }
+    if entries.is_empty() {
+        if pending {
+            state
+                .messages
+                .write()
+                .push(view::Message::notice("No older messages.").in_channel(&channel));
+        }
 
-            _ => {
-                let _ = Message::send(
-                    &mut *state.stream.lock(),
-                    UserMessage::Text {
-                        message: input_state.trim().to_string(),
-                    },
-                );
-
-                state
-                    .messages
-                    .write()
-                    .push(view::Message::user(&state.config.nick, input_state.trim()));
-            }
+        return;
+    }
+
+    let nick = state.nick.read().clone();
+    let mut messages = state.messages.write();
+
+    for entry in entries {
+        insert_recorded(&mut messages, &nick, &channel, entry);
+    }
+}
+
+/// Places a recorded message before the newer ones of its channel, unless it is displayed
+/// already.
+///
+/// Own text is displayed right away, the recorded copy only gives it its id.
+fn insert_recorded(
+    messages: &mut Vec<view::Message>,
+    nick: &str,
+    channel: &str,
+    entry: RecordedMessage,
+) {
+    let in_channel = |message: &view::Message| message.channel.as_deref() == Some(channel);
+
+    if messages
+        .iter()
+        .any(|message| message.id == Some(entry.id) && in_channel(message))
+    {
+        return;
+    }
+
+    let recorded = view::Message::recorded(channel, entry);
+
+    if recorded.source == nick {
+        let own = messages.iter_mut().find(|message| {
+            message.id.is_none()
+                && matches!(message.kind, view::Kind::User | view::Kind::Action)
+                && message.source == recorded.source
+                && message.text == recorded.text
+                && in_channel(message)
+        });
+
+        if let Some(own) = own {
+            own.id = recorded.id;
+            own.ts = recorded.ts;
+            return;
         }
-    } else {
-        *state.input.write() = (*input_state).clone();
+    }
+
+    let position = messages
+        .iter()
+        .position(|message| in_channel(message) && message.id > recorded.id)
+        .unwrap_or(messages.len());
+
+    messages.insert(position, recorded);
+}
+
+/// Authenticates and joins the channels left behind when the connection was lost.
+fn authenticate(state: &State) {
+    let connection = match state.connection() {
+        Some(connection) => connection,
+        None => return,
+    };
+
+    // the password belongs to the configured nick only
+    let nick = state.nick.read().clone();
+    let password = state
+        .config
+        .password
+        .as_ref()
+        .map(Password::as_str)
+        .filter(|_| nick == state.config.nick);
+
+    let _ = connection.authenticate(&nick, password);
+
+    let mut channels = state.members.read().keys().cloned().collect::<Vec<_>>();
+
+    if channels.is_empty() {
+        channels.push(state.config.channel.clone());
+    }
+
+    for channel in channels {
+        let _ = connection.join(&channel);
+    }
+}
+
+/// Sends what was typed while offline, now that the server accepted us.
+fn go_online(state: &State) {
+    *state.status.write() = Status::Online;
+
+    let outbox = std::mem::take(&mut *state.outbox.write());
+
+    if let Some(connection) = state.connection() {
+        for message in outbox {
+            let _ = connection.send(message);
+        }
+    }
+}
+
+/// Describes the end of a ban or mute given in milliseconds since the Unix epoch.
+fn describe_until(until: Option<i64>) -> String {
+    match until.and_then(|until| chrono::Utc.timestamp_millis_opt(until).single()) {
+        Some(until) => format!("until {}", until.format("%R, %d. %B")),
+        None => "indefinitely".to_string(),
     }
 }
 
-fn handle_server_message(state: &State, message: Message) {
-    use lvchat_core::*;
+fn handle_server_event(state: &State, event: Event) {
+    match event {
+        Event::Hello(hello) => {
+            log::info!(
+                "Remote host runs {} (protocol revision {})",
+                hello.software,
+                hello.protocol_version
+            );
+        }
+
+        Event::AuthRequested => {
+            let session = state.session.read().clone();
 
-    match message {
-        Message::User(_user_message) => todo!("Reject"),
+            match session {
+                Some(token) => {
+                    if let Some(connection) = state.connection() {
+                        let _ = connection.resume(&token);
+                    }
+                }
 
-        Message::Server(server_message) => match server_message {
-            ServerMessage::Auth => {
-                let _ = Message::send(
-                    &mut *state.stream.lock(),
-                    UserMessage::Auth {
-                        nick: state.config.nick.clone(),
-                    },
-                );
+                None => authenticate(state),
             }
+        }
+
+        Event::Session(token) => {
+            *state.session.write() = Some(token);
+
+            go_online(state);
+        }
+
+        Event::Resumed { nick, channels } => {
+            let text = if channels.is_empty() {
+                format!("Resumed session as {}", nick)
+            } else {
+                format!("Resumed session as {} in {}", nick, channels.join(", "))
+            };
+
+            state.messages.write().push(view::Message::notice(text));
+
+            *state.nick.write() = nick;
+
+            go_online(state);
+        }
+
+        Event::Notice(message) => {
+            state.messages.write().push(view::Message::notice(message));
+        }
+
+        Event::MessageOfTheDay(message) => {
+            *state.motd.write() = message;
+        }
 
-            ServerMessage::Notice { message } => {
-                state.messages.write().push(view::Message::notice(message));
+        Event::UserList(mut users) => {
+            users.insert(0, state.nick.read().clone());
+
+            *state.users.write() = users;
+        }
+
+        Event::MemberList { channel, users } => {
+            if state.joining.read().as_ref() == Some(&channel) {
+                *state.joining.write() = None;
+                *state.channel.write() = Some(channel.clone());
+            } else {
+                state.channel.write().get_or_insert_with(|| channel.clone());
             }
 
-            ServerMessage::Refer {
-                user,
-                message: user_message,
-            } => match user_message {
-                UserMessage::Auth { nick } => {
-                    if user != nick {
-                        state.messages.write().push(view::Message::notice(format!(
-                            "{} changed nick to {}",
-                            user, nick
-                        )));
-
-                        for user in state.users.write().iter_mut() {
-                            if user == &nick {
-                                *user = nick.clone();
-                            }
-                        }
-                    } else {
-                        state
-                            .messages
-                            .write()
-                            .push(view::Message::notice(format!("User joined: {}", nick)));
-
-                        state.users.write().push(nick);
+            state.members.write().insert(channel, users);
+        }
+
+        Event::History { channel, messages } => {
+            insert_history(state, channel, messages);
+        }
+
+        Event::Connected { nick } => {
+            state
+                .messages
+                .write()
+                .push(view::Message::notice(format!("User connected: {}", nick)));
+
+            state.users.write().push(nick);
+        }
+
+        Event::NickChanged { from, to } => {
+            let text = if *state.nick.read() == from {
+                *state.nick.write() = to.clone();
+
+                format!("You are now known as {}", to)
+            } else {
+                format!("{} changed nick to {}", from, to)
+            };
+
+            state.messages.write().push(view::Message::notice(text));
+
+            for name in state.users.write().iter_mut() {
+                if name == &from {
+                    *name = to.clone();
+                }
+            }
+
+            for members in state.members.write().values_mut() {
+                for name in members.iter_mut() {
+                    if name == &from {
+                        *name = to.clone();
                     }
                 }
+            }
+        }
 
-                UserMessage::Leave { message: _ } => {
-                    state
-                        .messages
-                        .write()
-                        .push(view::Message::notice(format!("User left: {}", user)));
+        Event::Left { nick, message: _ } => {
+            state
+                .messages
+                .write()
+                .push(view::Message::notice(format!("User left: {}", nick)));
 
-                    let mut users = state.users.write();
-                    let pos = users
-                        .iter()
-                        .position(|user_x| user_x == &user)
-                        .expect("User position (leaving)");
+            state.users.write().retain(|name| name != &nick);
 
-                    users.remove(pos);
-                }
+            for members in state.members.write().values_mut() {
+                members.retain(|name| name != &nick);
+            }
+        }
 
-                UserMessage::RequestUserList => {}
-                UserMessage::Text { message } => {
-                    state
-                        .messages
-                        .write()
-                        .push(view::Message::user(user, message));
+        Event::Joined { channel, nick } => {
+            state
+                .messages
+                .write()
+                .push(view::Message::notice(format!("{} joined", nick)).in_channel(&channel));
+
+            state.members.write().entry(channel).or_default().push(nick);
+        }
+
+        Event::Parted {
+            channel,
+            nick,
+            message,
+        } => {
+            let text = match message {
+                Some(message) => format!("{} left ({})", nick, message),
+                None => format!("{} left", nick),
+            };
+
+            state
+                .messages
+                .write()
+                .push(view::Message::notice(text).in_channel(&channel));
+
+            if let Some(members) = state.members.write().get_mut(&channel) {
+                members.retain(|name| name != &nick);
+            }
+        }
+
+        Event::Text {
+            channel,
+            from,
+            message,
+        } => {
+            state
+                .messages
+                .write()
+                .push(view::Message::user(from, message).in_channel(channel));
+        }
+
+        Event::Recorded { channel, message } => {
+            let nick = state.nick.read().clone();
+
+            insert_recorded(&mut state.messages.write(), &nick, &channel, message);
+        }
+
+        Event::PrivateText { from, to, message } => {
+            state
+                .messages
+                .write()
+                .push(view::Message::private(from, to, message));
+        }
+
+        Event::Kicked { nick, by, reason } => {
+            if nick == *state.nick.read() {
+                match reason {
+                    Some(reason) => quit!("Kicked by {}: {}", by, reason),
+                    None => quit!("Kicked by {}.", by),
                 }
-                UserMessage::Voice { .. } => {}
-            },
-            ServerMessage::UserList { mut users } => {
-                users.insert(0, state.config.nick.clone());
+            }
 
-                *state.users.write() = users;
+            let text = match reason {
+                Some(reason) => format!("{} was kicked by {} ({})", nick, by, reason),
+                None => format!("{} was kicked by {}", nick, by),
+            };
+
+            state.messages.write().push(view::Message::notice(text));
+
+            state.users.write().retain(|name| name != &nick);
+
+            for members in state.members.write().values_mut() {
+                members.retain(|name| name != &nick);
             }
-        },
+        }
 
-        Message::Error(error_message) => match error_message {
-            ErrorMessage::AlreadyConnected => {
-                eprintln!("Already connected. Only one client per IP address allowed.");
-                std::process::exit(0);
+        Event::Banned {
+            mask,
+            by,
+            duration,
+            reason,
+        } => {
+            let mut text = format!("{} banned {}", by, mask);
+
+            if let Some(duration) = duration {
+                text.push_str(&format!(" for {}s", duration));
             }
-            ErrorMessage::NickNameInUse => {
-                eprintln!("Someone with that nickname is already connected.");
-                std::process::exit(0);
+
+            if let Some(reason) = reason {
+                text.push_str(&format!(" ({})", reason));
             }
-        },
+
+            state.messages.write().push(view::Message::notice(text));
+        }
+
+        Event::Muted { nick, by, duration } => {
+            let text = match duration {
+                Some(0) => format!("{} unmuted {}", by, nick),
+                Some(duration) => format!("{} muted {} for {}s", by, nick, duration),
+                None => format!("{} muted {}", by, nick),
+            };
+
+            state.messages.write().push(view::Message::notice(text));
+        }
+
+        // the connection is closed right after, and retried until the server is back
+        Event::Shutdown(message) => {
+            let text = match message {
+                Some(message) => format!("Remote host shut down: {}", message),
+                None => "Remote host shut down.".to_string(),
+            };
+
+            state.messages.write().push(view::Message::notice(text));
+        }
+
+        Event::Error(error) => handle_error(state, error),
+
+        Event::Disconnected => {}
     }
 }
+
+fn handle_error(state: &State, error: ErrorMessage) {
+    // once authenticated, nick errors are about /nick or /register and not fatal
+    let authenticated = state.session.read().is_some();
+
+    match error {
+        ErrorMessage::NickNameInUse if authenticated => {
+            state
+                .messages
+                .write()
+                .push(view::Message::notice("That nickname is already in use."));
+        }
+        ErrorMessage::InvalidCredentials | ErrorMessage::NickNameReserved if authenticated => {
+            state.messages.write().push(view::Message::notice(
+                "That nickname is registered to someone else.",
+            ));
+        }
+        ErrorMessage::AlreadyConnected => {
+            quit!("Already connected.");
+        }
+        ErrorMessage::NickNameInUse => {
+            quit!("Someone with that nickname is already connected.");
+        }
+        ErrorMessage::IncompatibleProtocol { min, max } => {
+            quit!(
+                "Remote host only supports protocol revisions {} to {}.",
+                min,
+                max
+            );
+        }
+        ErrorMessage::InvalidCredentials => {
+            quit!("Wrong password for that nickname.");
+        }
+        ErrorMessage::NickNameReserved => {
+            quit!("That nickname is registered. Pass its password with --password.");
+        }
+        ErrorMessage::AccountRequired => {
+            quit!("Remote host only admits registered accounts.");
+        }
+        ErrorMessage::NotOperator => {
+            state
+                .messages
+                .write()
+                .push(view::Message::notice("Only operators may do that."));
+        }
+        ErrorMessage::Banned { reason, until } => {
+            quit!(
+                "Banned {}{}",
+                describe_until(until),
+                reason
+                    .map(|reason| format!(": {}", reason))
+                    .unwrap_or_default()
+            );
+        }
+        ErrorMessage::Muted { until } => {
+            state.messages.write().push(view::Message::notice(format!(
+                "You are muted {}.",
+                describe_until(until)
+            )));
+        }
+        ErrorMessage::RateLimited { retry_after } => {
+            state.messages.write().push(view::Message::notice(format!(
+                "You are sending too fast, messages are dropped. Wait {:.1}s.",
+                retry_after as f64 / 1000.0
+            )));
+        }
+        ErrorMessage::InvalidSession => {
+            *state.session.write() = None;
+
+            authenticate(state);
+        }
+        ErrorMessage::InvalidChannelName { channel } => {
+            state.messages.write().push(view::Message::notice(format!(
+                "Invalid channel name: {}",
+                channel
+            )));
+        }
+        ErrorMessage::NoSuchNick { nick } => {
+            state
+                .messages
+                .write()
+                .push(view::Message::notice(format!("No such nick: {}", nick)));
+        }
+        ErrorMessage::NotOnChannel { channel } => {
+            state
+                .messages
+                .write()
+                .push(view::Message::notice(format!("Not in channel {}", channel)));
+        }
+    }
+}
+
+#[test]
+fn show_recorded_messages_once() {
+    use structopt::StructOpt;
+
+    let state = State::new(Config::from_iter(&["lvchat-client", "--nick", "bob"]));
+    let lobby = "#lobby".to_owned();
+    let recorded = |id, user: &str, message: &str| RecordedMessage {
+        id,
+        timestamp: 1_600_000_000_000 + id as i64,
+        user: user.to_owned(),
+        message: message.to_owned(),
+    };
+    let history = |messages| Event::History {
+        channel: lobby.clone(),
+        messages,
+    };
+    let live = |message| Event::Recorded {
+        channel: lobby.clone(),
+        message,
+    };
+    let texts = || {
+        state
+            .messages
+            .read()
+            .iter()
+            .filter(|message| message.kind == view::Kind::User)
+            .map(|message| (message.id, message.text.clone()))
+            .collect::<Vec<_>>()
+    };
+
+    handle_server_event(
+        &state,
+        Event::MemberList {
+            channel: lobby.clone(),
+            users: vec!["alice".to_owned(), "bob".to_owned()],
+        },
+    );
+    handle_server_event(&state, history(vec![recorded(5, "alice", "earlier")]));
+    handle_server_event(&state, live(recorded(6, "alice", "hi")));
+
+    command::say(&state, "hello");
+    handle_server_event(&state, live(recorded(7, "bob", "hello")));
+
+    // rejoining replays what is displayed already
+    handle_server_event(
+        &state,
+        history(vec![
+            recorded(5, "alice", "earlier"),
+            recorded(6, "alice", "hi"),
+            recorded(7, "bob", "hello"),
+        ]),
+    );
+    handle_server_event(&state, history(vec![recorded(4, "alice", "first")]));
+
+    assert_eq!(
+        texts(),
+        vec![
+            (Some(4), "first".to_owned()),
+            (Some(5), "earlier".to_owned()),
+            (Some(6), "hi".to_owned()),
+            (Some(7), "hello".to_owned()),
+        ]
+    );
+}
@@ -1,8 +1,28 @@
+use chrono::TimeZone;
+
+use lvchat_core::RecordedMessage;
+
+/// Start of a text sent by `/me`, shown as an action by the rest of the line.
+pub const ACTION_PREFIX: &str = "/me ";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Kind {
+    User,
+    Action,
+    Notice,
+    Private { to: String },
+}
+
 #[derive(Debug, Clone)]
 pub struct Message {
     pub ts: chrono::DateTime<chrono::Utc>,
+    pub kind: Kind,
     pub source: String,
+    pub channel: Option<String>,
     pub text: String,
+
+    /// Id the server recorded the message under, which orders the messages of a channel.
+    pub id: Option<u64>,
 }
 
 impl Message {
@@ -11,29 +31,91 @@ impl Message {
         S: AsRef<str>,
         T: AsRef<str>,
     {
+        let kind = if text.as_ref().starts_with(ACTION_PREFIX) {
+            Kind::Action
+        } else {
+            Kind::User
+        };
+
         Self {
             ts: chrono::Utc::now(),
+            kind,
             source: source.as_ref().to_string(),
+            channel: None,
             text: text.as_ref().to_string(),
+            id: None,
+        }
+    }
+
+    /// Channel text as the server recorded it, stamped with the server's time.
+    pub fn recorded<C: AsRef<str>>(channel: C, message: RecordedMessage) -> Self {
+        let ts = chrono::Utc
+            .timestamp_millis_opt(message.timestamp)
+            .single()
+            .unwrap_or_else(chrono::Utc::now);
+
+        Self {
+            id: Some(message.id),
+            ..Self::user(message.user, message.message)
+                .in_channel(channel)
+                .at(ts)
         }
     }
 
     pub fn notice<T: AsRef<str>>(text: T) -> Self {
         Self {
             ts: chrono::Utc::now(),
+            kind: Kind::Notice,
             source: "NOTICE".to_string(),
+            channel: None,
             text: text.as_ref().to_string(),
+            id: None,
         }
     }
+
+    pub fn private<S, R, T>(source: S, to: R, text: T) -> Self
+    where
+        S: AsRef<str>,
+        R: AsRef<str>,
+        T: AsRef<str>,
+    {
+        Self {
+            ts: chrono::Utc::now(),
+            kind: Kind::Private {
+                to: to.as_ref().to_string(),
+            },
+            source: source.as_ref().to_string(),
+            channel: None,
+            text: text.as_ref().to_string(),
+            id: None,
+        }
+    }
+}
+
+impl Message {
+    pub fn at(mut self, ts: chrono::DateTime<chrono::Utc>) -> Self {
+        self.ts = ts;
+        self
+    }
+
+    pub fn in_channel<C: AsRef<str>>(mut self, channel: C) -> Self {
+        self.channel = Some(channel.as_ref().to_string());
+        self
+    }
 }
 
-impl ToString for Message {
-    fn to_string(&self) -> String {
-        format!(
-            "[{}] <{}> {}",
-            self.ts.format("%R, %d. %B"),
-            self.source,
-            self.text
-        )
+impl std::fmt::Display for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "[{}] ", self.ts.format("%R, %d. %B"))?;
+
+        if let Some(ref channel) = self.channel {
+            write!(f, "{} ", channel)?;
+        }
+
+        match self.kind {
+            Kind::Private { ref to } => write!(f, "*{} -> {}* {}", self.source, to, self.text),
+            Kind::Action => write!(f, "* {} {}", self.source, &self.text[ACTION_PREFIX.len()..]),
+            _ => write!(f, "<{}> {}", self.source, self.text),
+        }
     }
 }
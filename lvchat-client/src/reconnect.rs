@@ -0,0 +1,148 @@
+//! Connecting to the server in the background, and again whenever the connection is lost.
+
+use std::{
+    io, panic, thread,
+    time::{Duration, Instant},
+};
+
+use flume::{Receiver, Sender};
+use parking_lot::RwLock;
+use rand::Rng;
+
+use lvchat_client::{Connection, Endpoint, Event};
+
+/// Delay before retrying after the first failure, doubled with every further one.
+const INITIAL_DELAY: Duration = Duration::from_millis(500);
+
+/// Longest delay between attempts.
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// State of the connection, as shown in the status bar.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    /// Connecting or authenticating.
+    Connecting,
+    Online,
+
+    /// Waiting to retry after the connection was lost or couldn't be made.
+    Offline {
+        reason: String,
+        retry_at: Instant,
+    },
+}
+
+pub type Attempt = io::Result<(Connection, Receiver<Event>)>;
+
+/// Connects on a background thread, retrying with backoff until it succeeds.
+pub struct Reconnect {
+    endpoint: Endpoint,
+
+    /// Attempts failed in a row, including connections lost before getting online
+    failures: u32,
+
+    /// When to start the next attempt, `None` while one is running or connected
+    retry_at: Option<Instant>,
+}
+
+impl Reconnect {
+    /// Connects to `endpoint` right away.
+    pub fn new(endpoint: Endpoint) -> Self {
+        Reconnect {
+            endpoint,
+            failures: 0,
+            retry_at: Some(Instant::now()),
+        }
+    }
+
+    /// Schedules reconnecting after the connection was lost, backing off unless it was `online`.
+    pub fn lost(&mut self, online: bool, status: &RwLock<Status>) {
+        if online {
+            self.failures = 0;
+        } else {
+            self.failures += 1;
+        }
+
+        self.retry("connection lost".to_string(), status);
+    }
+
+    /// Starts an attempt when due, sending its outcome through `wake` once it's done.
+    pub fn poll<T: Send + 'static>(
+        &mut self,
+        status: &RwLock<Status>,
+        wake: &Sender<T>,
+        wrap: fn(Attempt) -> T,
+    ) {
+        if self.retry_at.is_some_and(|at| at <= Instant::now()) {
+            let wake = wake.clone();
+            let endpoint = self.endpoint.clone();
+
+            thread::spawn(move || {
+                let attempt = panic::catch_unwind(|| Connection::connect(&endpoint))
+                    .unwrap_or_else(|_| Err(io::Error::other("connecting thread panicked")));
+
+                let _ = wake.send(wrap(attempt));
+            });
+
+            self.retry_at = None;
+
+            *status.write() = Status::Connecting;
+        }
+    }
+
+    /// When the next attempt is due, `None` while one is running or connected.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.retry_at
+    }
+
+    /// Returns the connection if the attempt succeeded, otherwise schedules the next one.
+    ///
+    /// The status stays `Connecting` until the caller got online.
+    pub fn finish(
+        &mut self,
+        attempt: Attempt,
+        status: &RwLock<Status>,
+    ) -> Option<(Connection, Receiver<Event>)> {
+        match attempt {
+            Ok(connection) => Some(connection),
+
+            Err(e) => {
+                log::debug!("Failed to connect to {}: {}", self.endpoint, e);
+
+                self.failures += 1;
+                self.retry(e.to_string(), status);
+
+                None
+            }
+        }
+    }
+
+    fn retry(&mut self, reason: String, status: &RwLock<Status>) {
+        let retry_at = Instant::now() + backoff(self.failures, rand::thread_rng().gen());
+
+        self.retry_at = Some(retry_at);
+
+        *status.write() = Status::Offline { reason, retry_at };
+    }
+}
+
+/// Delay before retrying after `failures` failed attempts in a row.
+///
+/// `jitter` between 0 and 1 takes off up to half of it, so clients dropped at once don't all
+/// come back at once.
+pub fn backoff(failures: u32, jitter: f64) -> Duration {
+    let delay = INITIAL_DELAY
+        .saturating_mul(2u32.saturating_pow(failures))
+        .min(MAX_DELAY);
+
+    delay.mul_f64(1.0 - jitter.clamp(0.0, 1.0) / 2.0)
+}
+
+#[test]
+fn back_off_exponentially() {
+    assert_eq!(backoff(0, 0.0), Duration::from_millis(500));
+    assert_eq!(backoff(0, 1.0), Duration::from_millis(250));
+    assert_eq!(backoff(3, 0.0), Duration::from_secs(4));
+    assert_eq!(backoff(3, 0.5), Duration::from_secs(3));
+    assert_eq!(backoff(7, 0.0), MAX_DELAY);
+    assert_eq!(backoff(u32::MAX, 1.0), MAX_DELAY / 2);
+}
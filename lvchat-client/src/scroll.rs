@@ -0,0 +1,168 @@
+//! Scrollback of the message pane.
+
+use std::ops::Range;
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Lines a mouse wheel step scrolls.
+pub const WHEEL_LINES: usize = 3;
+
+/// Position of the message pane within the wrapped messages.
+#[derive(Debug, Clone, Default)]
+pub struct Scrollback {
+    /// Lines scrolled up from the bottom, 0 follows new messages
+    offset: usize,
+
+    /// Wrapped lines and pane height of the last render
+    lines: usize,
+    height: usize,
+
+    /// Messages there were when the bottom was last shown
+    seen: usize,
+}
+
+impl Scrollback {
+    pub fn scroll_up(&mut self, lines: usize) {
+        self.offset = (self.offset + lines).min(max_offset(self.lines, self.height));
+    }
+
+    pub fn scroll_down(&mut self, lines: usize) {
+        self.offset = self.offset.saturating_sub(lines);
+    }
+
+    pub fn page_up(&mut self) {
+        self.scroll_up(self.height.saturating_sub(1).max(1));
+    }
+
+    pub fn page_down(&mut self) {
+        self.scroll_down(self.height.saturating_sub(1).max(1));
+    }
+
+    pub fn is_at_bottom(&self) -> bool {
+        self.offset == 0
+    }
+
+    /// Range of the `lines` wrapped from `messages` messages to show in a pane of `height` rows,
+    /// and how many of the messages arrived while scrolled up.
+    ///
+    /// While scrolled up the view stays put as messages arrive, and the bottom row is left to
+    /// the caller for an indicator.
+    pub fn layout(
+        &mut self,
+        lines: usize,
+        height: usize,
+        messages: usize,
+    ) -> (Range<usize>, usize) {
+        if self.offset > 0 && lines > self.lines {
+            self.offset += lines - self.lines;
+        }
+
+        self.lines = lines;
+        self.height = height;
+        self.offset = self.offset.min(max_offset(lines, height));
+
+        let rows = if self.offset > 0 {
+            height.saturating_sub(1)
+        } else {
+            self.seen = messages;
+            height
+        };
+
+        let end = lines - self.offset;
+
+        (
+            end.saturating_sub(rows)..end,
+            messages.saturating_sub(self.seen),
+        )
+    }
+}
+
+/// Offset showing the first line at the top, below which a row is taken by the indicator.
+fn max_offset(lines: usize, height: usize) -> usize {
+    if lines > height {
+        lines - height.saturating_sub(1)
+    } else {
+        0
+    }
+}
+
+/// Breaks `text` into lines of at most `width` columns, between words where possible.
+pub fn wrap(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines = vec![];
+
+    for paragraph in text.split('\n') {
+        let mut line = String::new();
+        let mut line_width = 0;
+
+        for word in paragraph.split_word_bounds() {
+            let word_width = word.width();
+
+            if line_width + word_width > width && line_width > 0 {
+                lines.push(line.trim_end().to_owned());
+                line.clear();
+                line_width = 0;
+
+                if word.trim().is_empty() {
+                    continue;
+                }
+            }
+
+            if word_width <= width {
+                line.push_str(word);
+                line_width += word_width;
+
+                continue;
+            }
+
+            // longer than a whole line, so it has to be split anywhere
+            for grapheme in word.graphemes(true) {
+                if line_width + grapheme.width() > width && line_width > 0 {
+                    lines.push(std::mem::take(&mut line));
+                    line_width = 0;
+                }
+
+                line.push_str(grapheme);
+                line_width += grapheme.width();
+            }
+        }
+
+        lines.push(line);
+    }
+
+    lines
+}
+
+#[test]
+fn wrap_between_words() {
+    assert_eq!(
+        wrap("<alice> hello there, general kenobi", 14),
+        vec!["<alice> hello", "there, general", "kenobi"]
+    );
+    assert_eq!(wrap("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+    assert_eq!(wrap("日本語", 4), vec!["日本", "語"]);
+    assert_eq!(wrap("", 4), vec![""]);
+}
+
+#[test]
+fn hold_position_while_scrolled_up() {
+    let mut scroll = Scrollback::default();
+
+    assert_eq!(scroll.layout(30, 10, 30), (20..30, 0));
+
+    scroll.page_up();
+    assert_eq!(scroll.layout(30, 10, 30), (12..21, 0));
+
+    // new messages don't move the view but are counted
+    assert_eq!(scroll.layout(32, 10, 32), (12..21, 2));
+
+    scroll.scroll_up(100);
+    assert_eq!(scroll.layout(32, 10, 32), (0..9, 2));
+
+    scroll.page_down();
+    scroll.page_down();
+    scroll.page_down();
+    assert!(scroll.is_at_bottom());
+    assert_eq!(scroll.layout(32, 10, 32), (22..32, 0));
+}
@@ -1,9 +1,18 @@
-use std::{net::TcpStream, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use parking_lot::RwLock;
 
-use parking_lot::{Mutex, RwLock};
+use lvchat_client::Connection;
+use lvchat_core::UserMessage;
 
 use crate::{
     config::Config,
+    editor::LineEditor,
+    reconnect::Status,
+    scroll::Scrollback,
     view::{Message, User},
 };
 
@@ -11,26 +20,75 @@ use crate::{
 pub struct State {
     pub config: Arc<Config>,
 
+    /// Own nick, which starts out as the configured one
+    pub nick: Arc<RwLock<String>>,
+
     pub users: Arc<RwLock<Vec<User>>>,
+
+    /// Channel text input is sent to
+    pub channel: Arc<RwLock<Option<String>>>,
+    pub members: Arc<RwLock<HashMap<String, Vec<User>>>>,
+
+    /// Channel to switch to once it has been joined
+    pub joining: Arc<RwLock<Option<String>>>,
+
+    /// Channels older history was requested for by the user
+    pub history_pending: Arc<RwLock<HashSet<String>>>,
+
     pub messages: Arc<RwLock<Vec<Message>>>,
+    pub scroll: Arc<RwLock<Scrollback>>,
+
+    /// Message of the day, shown above the messages
+    pub motd: Arc<RwLock<Option<String>>>,
+
+    pub input: Arc<RwLock<LineEditor>>,
+
+    /// Token to resume the session with after reconnecting
+    pub session: Arc<RwLock<Option<String>>>,
+
+    /// Connection to the server, `None` while reconnecting
+    pub connection: Arc<RwLock<Option<Connection>>>,
+    pub status: Arc<RwLock<Status>>,
 
-    pub input: Arc<RwLock<String>>,
-    pub stream: Arc<Mutex<TcpStream>>,
+    /// Messages typed while not online, sent once back
+    pub outbox: Arc<RwLock<Vec<UserMessage>>>,
 }
 
 impl State {
-    pub fn new(config: Config, stream: TcpStream) -> Self {
+    pub fn new(config: Config) -> Self {
         let nick = config.nick.clone();
         let config = Arc::new(config);
 
         State {
             config,
 
+            nick: Arc::new(RwLock::new(nick.clone())),
+
             users: Arc::new(RwLock::new(vec![nick])),
+
+            channel: Arc::new(RwLock::new(None)),
+            members: Arc::new(RwLock::new(HashMap::new())),
+
+            joining: Arc::new(RwLock::new(None)),
+
+            history_pending: Arc::new(RwLock::new(HashSet::new())),
+
             messages: Arc::new(RwLock::new(vec![])),
+            scroll: Arc::new(RwLock::new(Scrollback::default())),
 
-            input: Arc::new(RwLock::new(String::new())),
-            stream: Arc::new(Mutex::new(stream)),
+            motd: Arc::new(RwLock::new(None)),
+
+            input: Arc::new(RwLock::new(LineEditor::default())),
+
+            session: Arc::new(RwLock::new(None)),
+            connection: Arc::new(RwLock::new(None)),
+            status: Arc::new(RwLock::new(Status::Connecting)),
+
+            outbox: Arc::new(RwLock::new(vec![])),
         }
     }
+
+    pub fn connection(&self) -> Option<Connection> {
+        self.connection.read().clone()
+    }
 }
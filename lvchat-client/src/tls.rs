@@ -0,0 +1,409 @@
+use std::{
+    convert::TryFrom,
+    io::{self, ErrorKind, Read, Write},
+    net::{Shutdown, TcpStream},
+    path::PathBuf,
+    sync::Arc,
+};
+
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+use parking_lot::Mutex;
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider},
+    pki_types::{pem::PemObject, CertificateDer, ServerName, UnixTime},
+    ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use sha2::{Digest, Sha256};
+
+/// How the server's certificate is checked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trust {
+    /// PEM encoded CA certificate the server's certificate must be issued by.
+    Ca(PathBuf),
+
+    /// SHA-256 fingerprint of the server's certificate, trusted without a CA.
+    Fingerprint(String),
+}
+
+/// Connection to the server, encrypted if a `Trust` was given.
+///
+/// Clones made with `try_clone` share the connection, so one thread can block reading while
+/// another writes.
+#[derive(Debug)]
+pub enum Stream {
+    Plain(TcpStream),
+    Tls(TlsStream),
+
+    /// Local connection through a Unix domain socket.
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    /// Wraps `sock` into TLS if `trust` is given, completing the handshake right away.
+    pub fn new(sock: TcpStream, host: &str, trust: Option<&Trust>) -> io::Result<Self> {
+        let tls = match trust {
+            Some(trust) => client_config(trust)?,
+            None => return Ok(Stream::Plain(sock)),
+        };
+
+        let name = ServerName::try_from(host.to_owned())
+            .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+        let mut conn = ClientConnection::new(tls, name).map_err(io::Error::other)?;
+        let mut sock = sock;
+
+        while conn.is_handshaking() {
+            conn.complete_io(&mut sock)?;
+        }
+
+        Ok(Stream::Tls(TlsStream {
+            conn: Arc::new(Mutex::new(conn)),
+            sock,
+        }))
+    }
+
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(match self {
+            Stream::Plain(sock) => Stream::Plain(sock.try_clone()?),
+
+            Stream::Tls(tls) => Stream::Tls(TlsStream {
+                conn: tls.conn.clone(),
+                sock: tls.sock.try_clone()?,
+            }),
+
+            #[cfg(unix)]
+            Stream::Unix(sock) => Stream::Unix(sock.try_clone()?),
+        })
+    }
+
+    pub fn shutdown(&mut self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Stream::Plain(sock) => sock.shutdown(how),
+
+            Stream::Tls(tls) => {
+                tls.conn.lock().send_close_notify();
+
+                let _ = tls.flush();
+
+                tls.sock.shutdown(how)
+            }
+
+            #[cfg(unix)]
+            Stream::Unix(sock) => sock.shutdown(how),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(sock) => sock.read(buf),
+            Stream::Tls(tls) => tls.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(sock) => sock.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(sock) => sock.write(buf),
+            Stream::Tls(tls) => tls.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(sock) => sock.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(sock) => sock.flush(),
+            Stream::Tls(tls) => tls.flush(),
+            #[cfg(unix)]
+            Stream::Unix(sock) => sock.flush(),
+        }
+    }
+}
+
+/// TLS session over a socket, which unlike `rustls::StreamOwned` can be shared between threads.
+///
+/// Reading waits for records on the socket without holding the session, which is only locked
+/// to decrypt them or to encrypt and send what's written.
+#[derive(Debug)]
+pub struct TlsStream {
+    conn: Arc<Mutex<ClientConnection>>,
+    sock: TcpStream,
+}
+
+impl TlsStream {
+    /// Sends whatever the session has encrypted so far.
+    fn write_tls(conn: &mut ClientConnection, mut sock: &TcpStream) -> io::Result<()> {
+        while conn.wants_write() {
+            conn.write_tls(&mut sock)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut records = [0u8; 4096];
+
+        loop {
+            match self.conn.lock().reader().read(buf) {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                read => return read,
+            }
+
+            let size = self.sock.read(&mut records)?;
+
+            if size == 0 {
+                return Ok(0);
+            }
+
+            let mut conn = self.conn.lock();
+            let mut received = &records[..size];
+
+            while !received.is_empty() {
+                conn.read_tls(&mut received)?;
+                conn.process_new_packets()
+                    .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+            }
+
+            TlsStream::write_tls(&mut conn, &self.sock)?;
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut conn = self.conn.lock();
+        let size = conn.writer().write(buf)?;
+
+        TlsStream::write_tls(&mut conn, &self.sock)?;
+
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut conn = self.conn.lock();
+
+        conn.writer().flush()?;
+
+        TlsStream::write_tls(&mut conn, &self.sock)
+    }
+}
+
+/// Builds the TLS configuration.
+///
+/// A pinned fingerprint replaces certificate chain validation, otherwise the server has to
+/// present a certificate issued by the given CA.
+pub fn client_config(trust: &Trust) -> io::Result<Arc<ClientConfig>> {
+    let provider = Arc::new(ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?;
+
+    let tls = match trust {
+        Trust::Fingerprint(fingerprint) => {
+            let verifier = PinnedCertVerifier::new(fingerprint, provider)?;
+
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(verifier))
+                .with_no_client_auth()
+        }
+
+        Trust::Ca(ca) => {
+            let mut roots = RootCertStore::empty();
+
+            for cert in CertificateDer::pem_file_iter(ca)
+                .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?
+            {
+                let cert = cert.map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+
+                roots
+                    .add(cert)
+                    .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+            }
+
+            builder.with_root_certificates(roots).with_no_client_auth()
+        }
+    };
+
+    Ok(Arc::new(tls))
+}
+
+/// SHA-256 fingerprint of a DER encoded certificate, as colon separated hex.
+pub fn fingerprint(cert: &[u8]) -> String {
+    Sha256::digest(cert)
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Trusts exactly the certificate with the given fingerprint, like a self-signed one.
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    fingerprint: String,
+    provider: Arc<CryptoProvider>,
+}
+
+impl PinnedCertVerifier {
+    fn new(fingerprint: &str, provider: Arc<CryptoProvider>) -> io::Result<Self> {
+        let normalized = fingerprint.replace(':', "").to_uppercase();
+
+        if normalized.len() != 64 || !normalized.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "Fingerprint must be 32 hex encoded bytes",
+            ));
+        }
+
+        let fingerprint = normalized
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| String::from_utf8_lossy(pair).into_owned())
+            .collect::<Vec<_>>()
+            .join(":");
+
+        Ok(PinnedCertVerifier {
+            fingerprint,
+            provider,
+        })
+    }
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if fingerprint(end_entity) == self.fingerprint {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(format!(
+                "Certificate fingerprint {} doesn't match the pinned one",
+                fingerprint(end_entity)
+            )))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+#[test]
+fn pinned_fingerprint() {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+    let other = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+
+    let pinned = fingerprint(cert.cert.der()).to_lowercase().replace(':', "");
+    let verifier = PinnedCertVerifier::new(&pinned, Arc::new(ring::default_provider())).unwrap();
+    let name = ServerName::try_from("localhost").unwrap();
+
+    assert!(verifier
+        .verify_server_cert(cert.cert.der(), &[], &name, &[], UnixTime::now())
+        .is_ok());
+    assert!(verifier
+        .verify_server_cert(other.cert.der(), &[], &name, &[], UnixTime::now())
+        .is_err());
+    assert!(PinnedCertVerifier::new("00:11", Arc::new(ring::default_provider())).is_err());
+}
+
+#[test]
+fn write_while_reading_tls() {
+    use rustls::{pki_types::PrivateKeyDer, ServerConfig, ServerConnection};
+    use std::{net::TcpListener, thread};
+
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![certified.cert.der().clone()],
+            PrivateKeyDer::Pkcs8(certified.key_pair.serialize_der().into()),
+        )
+        .unwrap();
+
+    thread::spawn(move || {
+        let conn = ServerConnection::new(Arc::new(config)).unwrap();
+        let mut stream = rustls::StreamOwned::new(conn, listener.accept().unwrap().0);
+        let mut buf = [0u8; 64];
+
+        loop {
+            match stream.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(size) => {
+                    stream.write_all(&buf[..size]).unwrap();
+                    stream.flush().unwrap();
+                }
+            }
+        }
+    });
+
+    let trust = Trust::Fingerprint(fingerprint(certified.cert.der()));
+    let mut writer =
+        Stream::new(TcpStream::connect(addr).unwrap(), "localhost", Some(&trust)).unwrap();
+    let mut reader = writer.try_clone().unwrap();
+
+    let echoed = thread::spawn(move || {
+        let mut buf = [0u8; 5];
+
+        reader.read_exact(&mut buf).unwrap();
+        buf
+    });
+
+    thread::sleep(std::time::Duration::from_millis(50));
+
+    writer.write_all(b"hello").unwrap();
+    writer.flush().unwrap();
+
+    assert_eq!(&echoed.join().unwrap(), b"hello");
+
+    writer.shutdown(Shutdown::Both).unwrap();
+}
@@ -1,16 +1,27 @@
-use std::io::{stdout, Stdout};
+use std::{
+    io::{stdout, Stdout, Write},
+    time::Instant,
+};
 
+use crossterm::{
+    event::{DisableMouseCapture, EnableMouseCapture},
+    execute, terminal,
+};
 use tui::{
     layout::{Constraint, Direction, Layout, Rect},
+    style::{Color, Style},
     terminal::Terminal,
     widgets::{Block, Borders, List, Paragraph, Text},
 };
 
-pub use crate::message::Message;
-use crate::state::State;
+pub use crate::message::{Kind, Message};
+use crate::{reconnect::Status, scroll::wrap, state::State};
 
 pub type User = String;
 
+/// Most lines of the message of the day shown before it is cut off.
+const MAX_MOTD_LINES: usize = 10;
+
 pub struct View {
     //#[cfg(target_os = "windows")]
     terminal: Terminal<tui::backend::CrosstermBackend<Stdout>>,
@@ -23,25 +34,65 @@ impl View {
         let _ = self.terminal.clear();
     }
 
-    pub fn update(&mut self, state: &State) {}
+    pub fn update(&mut self, _state: &State) {}
 
     pub fn render(&mut self, state: &State) {
-        let user_list_items = state.users.read().iter().cloned().collect::<Vec<_>>();
+        let channel = state.channel.read().clone();
+
+        // members of the active channel, or everyone connected while in no channel
+        let user_list_items = match channel {
+            Some(ref channel) => state
+                .members
+                .read()
+                .get(channel)
+                .cloned()
+                .unwrap_or_default(),
+
+            None => state.users.read().iter().cloned().collect::<Vec<_>>(),
+        };
         let user_list_view = List::new(user_list_items.iter().map(Text::raw));
 
         let message_list_items = state.messages.read().iter().cloned().collect::<Vec<_>>();
-        let message_list_view = List::new(
-            message_list_items
-                .iter()
-                .map(ToString::to_string)
-                .map(Text::raw),
-        )
-        .block(Block::default().borders(Borders::LEFT));
+        let message_list_block = Block::default()
+            .borders(Borders::LEFT)
+            .title(channel.as_deref().unwrap_or_default());
+        let mut scroll = state.scroll.write();
+
+        // the message of the day sits in its own box above the messages
+        let motd = state.motd.read().clone();
+        let motd_height = motd
+            .as_ref()
+            .map(|motd| motd.lines().count().min(MAX_MOTD_LINES) as u16 + 2)
+            .unwrap_or_default();
+        let motd_para = motd
+            .iter()
+            .map(|motd| Text::raw(format!("{}\n", motd)))
+            .collect::<Vec<_>>();
+        let motd_view = Paragraph::new(motd_para.iter()).wrap(true).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Message of the day"),
+        );
 
-        let message_input = state.input.read().clone();
+        let width = self
+            .terminal
+            .size()
+            .map(|size| size.width as usize)
+            .unwrap_or_default();
+        let (message_input, cursor) = {
+            let editor = state.input.read();
+            let (visible, cursor) = editor.visible(width);
+
+            (visible.to_owned(), cursor)
+        };
         let message_para_input = [Text::raw(message_input)];
-        let message_input_view =
-            Paragraph::new(message_para_input.iter()).block(Block::default().borders(Borders::TOP));
+        let (status, status_color) = describe_status(state);
+        let message_input_view = Paragraph::new(message_para_input.iter()).block(
+            Block::default()
+                .borders(Borders::TOP)
+                .title(&status)
+                .title_style(Style::default().fg(status_color)),
+        );
 
         let _ = self.terminal.draw(move |mut frame| {
             let (bottom, top) = {
@@ -67,16 +118,70 @@ impl View {
                 (layout.pop().unwrap(), layout.pop().unwrap())
             };
 
+            let (messages, motd_area) = {
+                let mut layout = Layout::default()
+                    .constraints([Constraint::Length(motd_height), Constraint::Min(0)])
+                    .direction(Direction::Vertical)
+                    .split(top_right);
+
+                (layout.pop().unwrap(), layout.pop().unwrap())
+            };
+
             frame.render_widget(user_list_view, top_left);
-            frame.render_widget(message_list_view, top_right);
-            frame.render_widget(message_input_view, bottom.clone());
+            if motd_height > 0 {
+                frame.render_widget(motd_view, motd_area);
+            }
+            // wrapped by hand, so scrolling can count the lines
+            let pane = message_list_block.inner(messages);
+            let lines = message_list_items
+                .iter()
+                .flat_map(|message| {
+                    let style = match message.kind {
+                        Kind::Private { .. } => Style::default().fg(Color::Magenta),
+                        _ => Style::default(),
+                    };
+
+                    wrap(&message.to_string(), pane.width as usize)
+                        .into_iter()
+                        .map(move |line| Text::styled(line, style))
+                })
+                .collect::<Vec<_>>();
+            let (visible, unread) =
+                scroll.layout(lines.len(), pane.height as usize, message_list_items.len());
+
+            frame.render_widget(
+                List::new(lines[visible].iter().cloned()).block(message_list_block),
+                messages,
+            );
+
+            if !scroll.is_at_bottom() && pane.height > 0 {
+                let indicator = match unread {
+                    0 => "-- More below, PageDown to follow --".to_string(),
+                    1 => "-- 1 new message below --".to_string(),
+                    unread => format!("-- {} new messages below --", unread),
+                };
+                let indicator = [Text::styled(
+                    indicator,
+                    Style::default().fg(Color::Black).bg(Color::Yellow),
+                )];
+
+                frame.render_widget(
+                    Paragraph::new(indicator.iter()),
+                    Rect {
+                        y: pane.bottom() - 1,
+                        height: 1,
+                        ..pane
+                    },
+                );
+            }
+            frame.render_widget(message_input_view, bottom);
         });
 
         let _ = self.terminal.set_cursor(
-            state.input.read().len() as u16,
+            cursor as u16,
             self.terminal
                 .size()
-                .map(|size| size.height - 2)
+                .map(|size| size.height.saturating_sub(2))
                 .unwrap_or_default(),
         );
     }
@@ -99,9 +204,62 @@ impl Default for View {
 
         let terminal = Terminal::new(backend).unwrap();
 
+        let _ = terminal::enable_raw_mode();
+        let _ = execute!(stdout(), EnableMouseCapture);
+
         View { terminal }
     }
 }
+
+impl Drop for View {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+/// Status bar text, colored by whether messages go out.
+fn describe_status(state: &State) -> (String, Color) {
+    let endpoint = state.config.endpoint();
+
+    let (mut text, color) = match &*state.status.read() {
+        Status::Online => (
+            format!(" {} on {}", state.nick.read(), endpoint),
+            Color::Green,
+        ),
+
+        Status::Connecting => (format!(" Connecting to {}", endpoint), Color::Yellow),
+
+        Status::Offline { reason, retry_at } => {
+            let wait = retry_at.saturating_duration_since(Instant::now());
+
+            (
+                format!(
+                    " Disconnected from {} ({}), retrying in {}s",
+                    endpoint,
+                    reason,
+                    wait.as_secs_f64().ceil()
+                ),
+                Color::Red,
+            )
+        }
+    };
+
+    match state.outbox.read().len() {
+        0 => {}
+        1 => text.push_str(", 1 message queued"),
+        queued => text.push_str(&format!(", {} messages queued", queued)),
+    }
+
+    text.push(' ');
+
+    (text, color)
+}
+
+/// Leaves raw mode, so the shell works again once the client exits.
+pub fn restore_terminal() {
+    let _ = execute!(stdout(), DisableMouseCapture);
+    let _ = terminal::disable_raw_mode();
+}
 /*
 fn create_user_list_view<'a>(state: &'a State) -> impl Widget + 'a {
     List::new(state.users.read().iter().cloned().map(Text::raw))
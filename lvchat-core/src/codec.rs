@@ -0,0 +1,123 @@
+//! `tokio_util` codec for the wire protocol, to drive `Framed` over any `AsyncRead`/`AsyncWrite`.
+
+use std::io;
+
+use bytes::{Buf, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{
+    frame::{self, FrameEncoder, HEADER_SIZE, MAX_FRAME_SIZE},
+    message::Message,
+};
+
+/// Turns frames into messages and back.
+#[derive(Debug, Clone, Copy)]
+pub struct MessageCodec {
+    max_frame_size: usize,
+}
+
+impl MessageCodec {
+    pub fn new(max_frame_size: usize) -> Self {
+        MessageCodec { max_frame_size }
+    }
+}
+
+impl Default for MessageCodec {
+    fn default() -> Self {
+        Self::new(MAX_FRAME_SIZE)
+    }
+}
+
+impl Decoder for MessageCodec {
+    type Item = Message;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Message>> {
+        let size = match frame::payload_size(src, self.max_frame_size)? {
+            Some(size) => size,
+            None => return Ok(None),
+        };
+
+        if src.len() < HEADER_SIZE + size {
+            src.reserve(HEADER_SIZE + size - src.len());
+
+            return Ok(None);
+        }
+
+        src.advance(HEADER_SIZE);
+
+        let payload = src.split_to(size);
+
+        Message::from_bytes(&payload)
+            .map(Some)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid message"))
+    }
+}
+
+impl<M: Into<Message>> Encoder<M> for MessageCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, message: M, dst: &mut BytesMut) -> io::Result<()> {
+        let payload = message.into().to_bytes();
+        let header = FrameEncoder::new(self.max_frame_size).header(&payload)?;
+
+        dst.reserve(HEADER_SIZE + payload.len());
+        dst.extend_from_slice(&header);
+        dst.extend_from_slice(&payload);
+
+        Ok(())
+    }
+}
+
+#[test]
+fn codec_matches_sync_framing() {
+    use bytes::BufMut;
+
+    use crate::message::{Server, User};
+
+    let mut codec = MessageCodec::default();
+    let mut wire = BytesMut::new();
+
+    codec
+        .encode(
+            User::Text {
+                channel: "#lobby".to_owned(),
+                message: "hi".to_owned(),
+            },
+            &mut wire,
+        )
+        .unwrap();
+
+    let mut sync = vec![];
+    Message::send(&mut sync, Server::Auth).unwrap();
+
+    let mut partial = BytesMut::new();
+    let mut messages = vec![];
+
+    for byte in wire.iter().chain(&sync) {
+        partial.put_u8(*byte);
+
+        while let Some(message) = codec.decode(&mut partial).unwrap() {
+            messages.push(message);
+        }
+    }
+
+    assert_eq!(
+        messages,
+        vec![
+            Message::User(User::Text {
+                channel: "#lobby".to_owned(),
+                message: "hi".to_owned()
+            }),
+            Message::Server(Server::Auth)
+        ]
+    );
+    assert_eq!(
+        Message::recv(&mut &wire[..]).unwrap(),
+        messages[0],
+        "Sync side reads what the codec wrote"
+    );
+
+    let mut oversized = BytesMut::from(&[0xFF, 0xFF, 0xFF, 0xFF][..]);
+    assert!(codec.decode(&mut oversized).is_err());
+}
@@ -0,0 +1,220 @@
+//! Length-prefixed framing of the lvchat wire protocol.
+//!
+//! Every frame on the wire is a big-endian `u32` holding the payload length, followed by the
+//! payload itself. Unlike a delimiter, this survives payloads containing arbitrary bytes.
+
+use std::io::{self, Read};
+
+/// Size of the length prefix preceding every frame.
+pub const HEADER_SIZE: usize = 4;
+
+/// Largest payload accepted by default, in bytes.
+pub const MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Frame exceeds the configured maximum frame size.
+    TooLarge { size: usize, max: usize },
+}
+
+impl std::error::Error for Error {}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::TooLarge { size, max } => {
+                write!(
+                    f,
+                    "Frame of {} bytes exceeds maximum of {} bytes",
+                    size, max
+                )
+            }
+        }
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// Size of the payload announced by the length prefix at the start of `data`, `None` if the
+/// prefix isn't complete yet.
+pub(crate) fn payload_size(data: &[u8], max_frame_size: usize) -> Result<Option<usize>, Error> {
+    if data.len() < HEADER_SIZE {
+        return Ok(None);
+    }
+
+    let mut header = [0u8; HEADER_SIZE];
+    header.copy_from_slice(&data[..HEADER_SIZE]);
+
+    let size = u32::from_be_bytes(header) as usize;
+
+    if size > max_frame_size {
+        return Err(Error::TooLarge {
+            size,
+            max: max_frame_size,
+        });
+    }
+
+    Ok(Some(size))
+}
+
+/// Reads exactly one frame from a blocking `reader`, returning its payload.
+pub fn read_frame<R: Read>(reader: &mut R, max_frame_size: usize) -> io::Result<Vec<u8>> {
+    let mut header = [0u8; HEADER_SIZE];
+    reader.read_exact(&mut header)?;
+
+    let size = payload_size(&header, max_frame_size)?.unwrap_or_default();
+
+    let mut payload = vec![0u8; size];
+    reader.read_exact(&mut payload)?;
+
+    Ok(payload)
+}
+
+/// Wraps payloads into frames.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameEncoder {
+    max_frame_size: usize,
+}
+
+impl FrameEncoder {
+    pub fn new(max_frame_size: usize) -> Self {
+        FrameEncoder { max_frame_size }
+    }
+
+    /// Appends the framed `payload` to `dst`.
+    pub fn encode(&self, payload: &[u8], dst: &mut Vec<u8>) -> Result<(), Error> {
+        let header = self.header(payload)?;
+
+        dst.reserve(HEADER_SIZE + payload.len());
+        dst.extend_from_slice(&header);
+        dst.extend_from_slice(payload);
+
+        Ok(())
+    }
+
+    /// Returns the header to put in front of `payload`.
+    pub fn header(&self, payload: &[u8]) -> Result<[u8; HEADER_SIZE], Error> {
+        if payload.len() > self.max_frame_size {
+            return Err(Error::TooLarge {
+                size: payload.len(),
+                max: self.max_frame_size,
+            });
+        }
+
+        Ok((payload.len() as u32).to_be_bytes())
+    }
+}
+
+impl Default for FrameEncoder {
+    fn default() -> Self {
+        Self::new(MAX_FRAME_SIZE)
+    }
+}
+
+/// Reassembles frames from arbitrarily split reads.
+#[derive(Debug, Clone)]
+pub struct FrameDecoder {
+    max_frame_size: usize,
+    buffer: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new(max_frame_size: usize) -> Self {
+        FrameDecoder {
+            max_frame_size,
+            buffer: vec![],
+        }
+    }
+
+    /// Feeds freshly read bytes into the decoder.
+    pub fn extend(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Number of buffered bytes not yet returned as a frame.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Returns the next complete payload, or `None` if more data is needed.
+    ///
+    /// An oversized frame leaves the stream out of sync, so the connection should be dropped.
+    pub fn decode(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        let size = match payload_size(&self.buffer, self.max_frame_size)? {
+            Some(size) => size,
+            None => return Ok(None),
+        };
+
+        if self.buffer.len() < HEADER_SIZE + size {
+            return Ok(None);
+        }
+
+        let payload = self.buffer[HEADER_SIZE..HEADER_SIZE + size].to_vec();
+
+        self.buffer.drain(..HEADER_SIZE + size);
+
+        Ok(Some(payload))
+    }
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new(MAX_FRAME_SIZE)
+    }
+}
+
+#[test]
+fn round_trip_partial_reads() {
+    let encoder = FrameEncoder::default();
+    let mut decoder = FrameDecoder::default();
+    let mut wire = vec![];
+
+    encoder.encode(b"first\r\nframe", &mut wire).unwrap();
+    encoder.encode(&[], &mut wire).unwrap();
+    encoder
+        .encode(&[0x0D, 0x0A, 0x00, 0xFF], &mut wire)
+        .unwrap();
+
+    let mut frames = vec![];
+
+    for byte in wire {
+        decoder.extend(&[byte]);
+
+        while let Some(frame) = decoder.decode().unwrap() {
+            frames.push(frame);
+        }
+    }
+
+    assert_eq!(
+        frames,
+        vec![
+            b"first\r\nframe".to_vec(),
+            vec![],
+            vec![0x0D, 0x0A, 0x00, 0xFF]
+        ]
+    );
+    assert_eq!(decoder.buffered(), 0);
+}
+
+#[test]
+fn rejects_oversized_frames() {
+    let encoder = FrameEncoder::new(4);
+    let mut decoder = FrameDecoder::new(4);
+    let mut wire = vec![];
+
+    assert_eq!(
+        encoder.encode(b"too long", &mut wire),
+        Err(Error::TooLarge { size: 8, max: 4 })
+    );
+
+    FrameEncoder::default()
+        .encode(b"too long", &mut wire)
+        .unwrap();
+    decoder.extend(&wire);
+
+    assert_eq!(decoder.decode(), Err(Error::TooLarge { size: 8, max: 4 }));
+}
@@ -1,7 +1,17 @@
 pub use crate::{
-    message::{Error as ErrorMessage, Message, Server as ServerMessage, User as UserMessage},
-    user::User,
+    frame::{FrameDecoder, FrameEncoder},
+    message::{
+        Error as ErrorMessage, Hello, HistoryEntry, Message, Password, RecordedMessage,
+        Server as ServerMessage, User as UserMessage,
+    },
+    user::{Peer, User},
 };
 
+#[cfg(feature = "tokio")]
+pub use crate::codec::MessageCodec;
+
+#[cfg(feature = "tokio")]
+pub mod codec;
+pub mod frame;
 pub mod message;
 pub mod user;
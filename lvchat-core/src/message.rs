@@ -1,10 +1,24 @@
-use std::{
-    io::{self, Write},
-    net::TcpStream,
-};
+use std::io::{self, Read, Write};
 
 use serde::{Deserialize, Serialize};
 
+use crate::frame::{self, FrameEncoder, MAX_FRAME_SIZE};
+
+/// Protocol revision spoken by this build.
+///
+/// Revision 2 added channels, 3 accounts and sessions, 4 operators and rate limiting, 5 ids of
+/// recorded messages.
+pub const PROTOCOL_VERSION: u32 = 5;
+
+/// Oldest protocol revision this build still understands.
+///
+/// Only raised when an existing variant changes, like `Auth` gaining a password in revision 3.
+/// Peers speaking an older revision in range aren't sent the variants added after it.
+pub const MIN_PROTOCOL_VERSION: u32 = 3;
+
+/// Capability of clients that want `Voice` streams relayed to them.
+pub const CAPABILITY_VOICE: &str = "voice";
+
 /// Enumeration of the network protocol lvchat is using
 #[repr(C)]
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -22,39 +36,393 @@ pub enum Message {
 #[repr(C)]
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum User {
-    Auth { nick: String },
-    Leave { message: Option<String> },
+    /// Must stay the first variant, so every revision can decode it.
+    Hello(Hello),
+
+    /// Claims `nick`, which requires its password if it belongs to a registered account.
+    Auth {
+        nick: String,
+        password: Option<Password>,
+    },
+    Leave {
+        message: Option<String>,
+    },
 
     RequestUserList,
 
-    Text { message: String },
+    Text {
+        channel: String,
+        message: String,
+    },
+
+    Voice {
+        channel: String,
+        stream: Vec<u8>,
+    },
+
+    Join {
+        channel: String,
+    },
+    Part {
+        channel: String,
+        message: Option<String>,
+    },
+
+    RequestMemberList {
+        channel: String,
+    },
+
+    PrivateText {
+        to: String,
+        message: String,
+    },
+
+    /// Asks for up to `limit` messages of `channel` sent before `before` (milliseconds since the
+    /// Unix epoch), or the newest ones if `None`.
+    RequestHistory {
+        channel: String,
+        before: Option<i64>,
+        limit: u32,
+    },
+
+    Ping {
+        token: u64,
+    },
+    Pong {
+        token: u64,
+    },
+
+    /// Authenticates by taking over a previous session instead of claiming a nick.
+    Resume {
+        token: String,
+    },
+
+    /// Creates an account for `nick` and claims it.
+    Register {
+        nick: String,
+        password: Password,
+    },
+
+    /// Becomes an operator with the server's operator password.
+    Oper {
+        password: Password,
+    },
+
+    /// Disconnects `nick`. Operators only.
+    Kick {
+        nick: String,
+        reason: Option<String>,
+    },
+
+    /// Disconnects and keeps out every address matching `mask`, like `10.0.*`, for `duration`
+    /// seconds or forever. Operators only.
+    Ban {
+        mask: String,
+        duration: Option<u64>,
+        reason: Option<String>,
+    },
 
-    Voice { stream: Vec<u8> },
+    /// Silences `nick` for `duration` seconds or until the server restarts, a duration of 0
+    /// lifts the mute. Operators only.
+    Mute {
+        nick: String,
+        duration: Option<u64>,
+    },
+
+    /// Asks for up to `limit` messages of `channel` recorded before the one with the id `before`,
+    /// or the newest ones if `None`.
+    RequestHistoryPage {
+        channel: String,
+        before: Option<u64>,
+        limit: u32,
+    },
 }
 
 #[repr(C)]
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Server {
-    // Shutdown { message: Option<String> },
+    /// Must stay the first variant, so every revision can decode it.
+    Hello(Hello),
 
-    // MessageOfTheDay { message: Option<String> },
-    Notice { message: String },
+    Notice {
+        message: String,
+    },
 
     Auth,
 
-    Refer { user: String, message: User },
+    Refer {
+        user: String,
+        message: User,
+    },
+
+    UserList {
+        users: Vec<String>,
+    },
+
+    MemberList {
+        channel: String,
+        users: Vec<String>,
+    },
+
+    /// Recorded messages of a channel, oldest first.
+    History {
+        channel: String,
+        messages: Vec<HistoryEntry>,
+    },
+
+    /// Must be answered with a `Pong` carrying the same token.
+    Ping {
+        token: u64,
+    },
+    Pong {
+        token: u64,
+    },
+
+    /// Opaque token a reconnecting client can `Resume` its session with.
+    Session {
+        token: String,
+    },
+
+    /// Session was taken over, rejoining `channels` as `nick`.
+    Resumed {
+        nick: String,
+        channels: Vec<String>,
+    },
+
+    /// Server is going down and closes the connection right after.
+    Shutdown {
+        message: Option<String>,
+    },
 
-    UserList { users: Vec<String> },
+    /// Sent after authentication, in place of the welcome notice.
+    MessageOfTheDay {
+        message: Option<String>,
+    },
+
+    /// Recorded messages of a channel, oldest first, replacing `History`.
+    HistoryPage {
+        channel: String,
+        messages: Vec<RecordedMessage>,
+    },
+
+    /// Text sent to a channel as it was recorded, in place of a `Refer` to the `Text`. Also sent
+    /// back to its sender, telling the id of its own message.
+    Recorded {
+        channel: String,
+        message: RecordedMessage,
+    },
 }
 
 #[repr(C)]
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Error {
     /// Client is already connected.
+    ///
+    /// No longer sent since reconnecting clients resume their session instead.
     AlreadyConnected,
 
     /// Requested nick is already in use
     NickNameInUse,
+
+    /// Client speaks a protocol revision outside of the supported range.
+    IncompatibleProtocol { min: u32, max: u32 },
+
+    /// Channel names start with `#` and contain no whitespace or commas.
+    InvalidChannelName { channel: String },
+
+    /// Client tried to act on a channel it hasn't joined.
+    NotOnChannel { channel: String },
+
+    /// No user with that nick is online.
+    NoSuchNick { nick: String },
+
+    /// Session to resume is unknown or expired.
+    InvalidSession,
+
+    /// Password doesn't match the account of the nick.
+    InvalidCredentials,
+
+    /// Nick belongs to a registered account and needs its password.
+    NickNameReserved,
+
+    /// Server only lets registered accounts in.
+    AccountRequired,
+
+    /// Command is reserved to operators.
+    NotOperator,
+
+    /// Address of the client is banned, until `until` (milliseconds since the Unix epoch) or
+    /// forever.
+    Banned {
+        reason: Option<String>,
+        until: Option<i64>,
+    },
+
+    /// Client may not send messages until `until`, or until the server restarts.
+    Muted { until: Option<i64> },
+
+    /// Client sends faster than allowed and the message was dropped. Sending is possible again
+    /// after `retry_after` milliseconds.
+    RateLimited { retry_after: u64 },
+}
+
+/// Password sent in the clear over the connection, kept out of debug output.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Password(pub String);
+
+impl Password {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for Password {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Password(***)")
+    }
+}
+
+impl<S: Into<String>> From<S> for Password {
+    fn from(password: S) -> Self {
+        Password(password.into())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Milliseconds since the Unix epoch.
+    pub timestamp: i64,
+    pub user: String,
+    pub message: String,
+}
+
+/// Channel message as recorded by the server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedMessage {
+    /// Increases with every message recorded, so it orders messages and pages through them.
+    pub id: u64,
+
+    /// Milliseconds since the Unix epoch.
+    pub timestamp: i64,
+    pub user: String,
+    pub message: String,
+}
+
+impl From<RecordedMessage> for HistoryEntry {
+    fn from(message: RecordedMessage) -> Self {
+        HistoryEntry {
+            timestamp: message.timestamp,
+            user: message.user,
+            message: message.message,
+        }
+    }
+}
+
+/// Greeting both sides exchange before authentication.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hello {
+    pub protocol_version: u32,
+    pub software: String,
+    pub capabilities: Vec<String>,
+}
+
+impl Hello {
+    pub fn new<S: Into<String>>(software: S) -> Self {
+        Hello {
+            protocol_version: PROTOCOL_VERSION,
+            software: software.into(),
+            capabilities: vec![],
+        }
+    }
+
+    pub fn is_compatible(&self) -> bool {
+        (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&self.protocol_version)
+    }
+
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+
+    /// Whether the peer that sent this greeting is able to decode `message`.
+    pub fn understands(&self, message: &Message) -> bool {
+        message.revision() <= self.protocol_version
+    }
+}
+
+/// Longest channel name allowed, including the leading `#`.
+pub const MAX_CHANNEL_NAME_LEN: usize = 32;
+
+pub fn is_valid_channel_name(channel: &str) -> bool {
+    channel.len() > 1
+        && channel.len() <= MAX_CHANNEL_NAME_LEN
+        && channel.starts_with('#')
+        && !channel.chars().any(|c| c.is_whitespace() || c == ',')
+}
+
+impl Message {
+    /// Protocol revision that introduced the message, or the oldest one still supported.
+    pub fn revision(&self) -> u32 {
+        match self {
+            Message::User(user) | Message::Server(Server::Refer { message: user, .. }) => {
+                user.revision()
+            }
+            Message::Server(Server::HistoryPage { .. })
+            | Message::Server(Server::Recorded { .. }) => 5,
+            Message::Server(_) => MIN_PROTOCOL_VERSION,
+            Message::Error(error) => error.revision(),
+        }
+    }
+
+    /// Translates the message for a peer speaking `revision`, `None` if it has no equivalent.
+    pub fn for_revision(self, revision: u32) -> Option<Self> {
+        if self.revision() <= revision {
+            return Some(self);
+        }
+
+        match self {
+            Message::Server(Server::HistoryPage { channel, messages }) if revision < 5 => {
+                Some(Message::Server(Server::History {
+                    channel,
+                    messages: messages.into_iter().map(Into::into).collect(),
+                }))
+            }
+
+            Message::Server(Server::Recorded { channel, message }) if revision < 5 => {
+                Some(Message::Server(Server::Refer {
+                    user: message.user,
+                    message: User::Text {
+                        channel,
+                        message: message.message,
+                    },
+                }))
+            }
+
+            _ => None,
+        }
+    }
+}
+
+impl User {
+    pub fn revision(&self) -> u32 {
+        match self {
+            User::Oper { .. } | User::Kick { .. } | User::Ban { .. } | User::Mute { .. } => 4,
+            User::RequestHistoryPage { .. } => 5,
+            _ => MIN_PROTOCOL_VERSION,
+        }
+    }
+}
+
+impl Error {
+    pub fn revision(&self) -> u32 {
+        match self {
+            Error::NotOperator
+            | Error::Banned { .. }
+            | Error::Muted { .. }
+            | Error::RateLimited { .. } => 4,
+            _ => MIN_PROTOCOL_VERSION,
+        }
+    }
 }
 
 impl Message {
@@ -68,13 +436,27 @@ impl Message {
 }
 
 impl Message {
-    pub fn send<M: Into<Self>>(stream: &mut TcpStream, message: M) -> io::Result<()> {
+    /// Serializes the message into a single length-prefixed frame.
+    pub fn to_frame(&self) -> io::Result<Vec<u8>> {
+        let mut data = vec![];
+
+        FrameEncoder::default().encode(&self.to_bytes(), &mut data)?;
+
+        Ok(data)
+    }
+
+    pub fn send<W: Write, M: Into<Self>>(stream: &mut W, message: M) -> io::Result<()> {
         let message: Self = message.into();
-        let mut data = message.to_bytes();
 
-        data.extend_from_slice(&[b'\r', b'\n']);
+        stream.write_all(&message.to_frame()?)
+    }
+
+    /// Waits for the next message on a blocking `stream`.
+    pub fn recv<R: Read>(stream: &mut R) -> io::Result<Self> {
+        let payload = frame::read_frame(stream, MAX_FRAME_SIZE)?;
 
-        stream.write_all(&data).map(|_| ())
+        Self::from_bytes(&payload)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid message"))
     }
 }
 
@@ -104,3 +486,147 @@ fn bin_conv() {
 
     assert_eq!(origin, deserialized);
 }
+
+#[test]
+fn send_recv_round_trip() {
+    let mut wire = vec![];
+
+    Message::send(
+        &mut wire,
+        User::Join {
+            channel: "#lobby".to_owned(),
+        },
+    )
+    .unwrap();
+    Message::send(&mut wire, Server::Auth).unwrap();
+
+    let mut wire = io::Cursor::new(wire);
+
+    assert_eq!(
+        Message::recv(&mut wire).unwrap(),
+        Message::User(User::Join {
+            channel: "#lobby".to_owned()
+        })
+    );
+    assert_eq!(
+        Message::recv(&mut wire).unwrap(),
+        Message::Server(Server::Auth)
+    );
+    assert_eq!(
+        Message::recv(&mut wire).unwrap_err().kind(),
+        io::ErrorKind::UnexpectedEof
+    );
+}
+
+#[test]
+fn hello_compatibility() {
+    let mut hello = Hello::new("test");
+
+    assert!(hello.is_compatible());
+
+    hello.protocol_version = PROTOCOL_VERSION + 1;
+    assert!(!hello.is_compatible());
+
+    hello.protocol_version = MIN_PROTOCOL_VERSION - 1;
+    assert!(!hello.is_compatible());
+}
+
+#[test]
+fn understand_up_to_revision() {
+    let mut hello = Hello::new("test");
+    let kick = User::Kick {
+        nick: "bob".to_owned(),
+        reason: None,
+    };
+
+    assert!(hello.understands(&Server::Auth.into()));
+    assert!(hello.understands(&kick.clone().into()));
+
+    hello.protocol_version = 3;
+
+    assert!(hello.is_compatible());
+    assert!(hello.understands(&Server::Auth.into()));
+    assert!(!hello.understands(&Error::NotOperator.into()));
+    assert!(!hello.understands(
+        &Server::Refer {
+            user: "alice".to_owned(),
+            message: kick,
+        }
+        .into()
+    ));
+}
+
+#[test]
+fn translate_history_for_older_revisions() {
+    let page = Message::Server(Server::HistoryPage {
+        channel: "#lobby".to_owned(),
+        messages: vec![RecordedMessage {
+            id: 7,
+            timestamp: 1000,
+            user: "alice".to_owned(),
+            message: "hi".to_owned(),
+        }],
+    });
+
+    assert_eq!(page.clone().for_revision(5), Some(page.clone()));
+    assert_eq!(
+        page.for_revision(4),
+        Some(Message::Server(Server::History {
+            channel: "#lobby".to_owned(),
+            messages: vec![HistoryEntry {
+                timestamp: 1000,
+                user: "alice".to_owned(),
+                message: "hi".to_owned(),
+            }],
+        }))
+    );
+    assert_eq!(Message::from(Error::NotOperator).for_revision(3), None);
+
+    let recorded = Message::Server(Server::Recorded {
+        channel: "#lobby".to_owned(),
+        message: RecordedMessage {
+            id: 8,
+            timestamp: 2000,
+            user: "bob".to_owned(),
+            message: "hello".to_owned(),
+        },
+    });
+
+    assert_eq!(
+        recorded.for_revision(4),
+        Some(Message::Server(Server::Refer {
+            user: "bob".to_owned(),
+            message: User::Text {
+                channel: "#lobby".to_owned(),
+                message: "hello".to_owned(),
+            },
+        }))
+    );
+}
+
+#[test]
+fn password_redacted() {
+    let auth = User::Auth {
+        nick: "alice".to_owned(),
+        password: Some("hunter2".into()),
+    };
+
+    assert!(!format!("{:?}", auth).contains("hunter2"));
+    assert_eq!(
+        bincode2::serialize(&Password::from("hunter2")).unwrap(),
+        bincode2::serialize("hunter2").unwrap()
+    );
+}
+
+#[test]
+fn channel_names() {
+    assert!(is_valid_channel_name("#lobby"));
+    assert!(!is_valid_channel_name("#"));
+    assert!(!is_valid_channel_name("lobby"));
+    assert!(!is_valid_channel_name("#a b"));
+    assert!(!is_valid_channel_name("#a,b"));
+    assert!(!is_valid_channel_name(&format!(
+        "#{}",
+        "a".repeat(MAX_CHANNEL_NAME_LEN)
+    )));
+}
@@ -1,12 +1,37 @@
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 
-// TODO: Make this work for both clients and the server. Clients won't have an addr.
+/// Where a user is connected from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Peer {
+    Tcp(SocketAddr),
+
+    /// Connected through a Unix domain socket on the same host.
+    Local,
+}
+
+impl Peer {
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self {
+            Self::Tcp(addr) => Some(addr.ip()),
+            Self::Local => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Peer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{}", addr.ip()),
+            Self::Local => write!(f, "local"),
+        }
+    }
+}
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum User {
-    Ghost { addr: SocketAddr },
+    Ghost { peer: Peer },
 
-    Authenticated { nick: String, addr: SocketAddr },
+    Authenticated { nick: String, peer: Peer },
 }
 
 impl User {
@@ -20,9 +45,9 @@ impl User {
 }
 
 impl User {
-    pub fn addr(&self) -> &SocketAddr {
+    pub fn peer(&self) -> &Peer {
         match self {
-            Self::Ghost { ref addr } | Self::Authenticated { ref addr, .. } => addr,
+            Self::Ghost { ref peer } | Self::Authenticated { ref peer, .. } => peer,
         }
     }
 
@@ -46,12 +71,12 @@ impl User {
 impl std::fmt::Display for User {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            Self::Authenticated { nick, addr } => {
-                write!(f, "{} @ {}", nick, addr.ip())
+            Self::Authenticated { nick, peer } => {
+                write!(f, "{} @ {}", nick, peer)
             }
 
-            Self::Ghost { addr } => {
-                write!(f, "{}", addr.ip())
+            Self::Ghost { peer } => {
+                write!(f, "{}", peer)
             }
         }
     }
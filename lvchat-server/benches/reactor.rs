@@ -0,0 +1,196 @@
+//! Measures CPU usage of an idle server and relay throughput under load.
+//!
+//! Run with `cargo bench -p lvchat-server`.
+
+use std::{
+    io,
+    net::{SocketAddr, TcpStream},
+    sync::{Arc, Barrier},
+    thread::{sleep, spawn},
+    time::{Duration, Instant},
+};
+
+use structopt::StructOpt;
+
+use lvchat_core::*;
+use lvchat_server::{
+    config::{Args, Config},
+    reactor::Reactor,
+    state::State,
+};
+
+const IDLE_CLIENTS: usize = 100;
+const IDLE_DURATION: Duration = Duration::from_secs(3);
+
+const CHANNEL: &str = "#bench";
+const SENDERS: usize = 8;
+const MESSAGES_PER_SENDER: usize = 5_000;
+
+struct Connection {
+    stream: TcpStream,
+}
+
+impl Connection {
+    fn open(addr: SocketAddr, n: usize) -> io::Result<Self> {
+        let mut connection = Connection {
+            stream: TcpStream::connect(addr)?,
+        };
+
+        Message::send(
+            &mut connection.stream,
+            UserMessage::Hello(Hello::new("lvchat-bench")),
+        )?;
+
+        while connection.recv()? != Message::Server(ServerMessage::Auth) {}
+
+        Message::send(
+            &mut connection.stream,
+            UserMessage::Auth {
+                nick: format!("client{}", n),
+                password: None,
+            },
+        )?;
+
+        while !matches!(
+            connection.recv()?,
+            Message::Server(ServerMessage::UserList { .. })
+        ) {}
+
+        Ok(connection)
+    }
+
+    fn join(&mut self, channel: &str) -> io::Result<()> {
+        Message::send(
+            &mut self.stream,
+            UserMessage::Join {
+                channel: channel.to_owned(),
+            },
+        )?;
+
+        while !matches!(
+            self.recv()?,
+            Message::Server(ServerMessage::MemberList { .. })
+        ) {}
+
+        Ok(())
+    }
+
+    fn recv(&mut self) -> io::Result<Message> {
+        Message::recv(&mut self.stream)
+    }
+}
+
+#[cfg(unix)]
+fn cpu_time() -> Option<Duration> {
+    let mut usage = unsafe { std::mem::zeroed::<libc::rusage>() };
+
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return None;
+    }
+
+    let to_duration = |time: libc::timeval| {
+        Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+    };
+
+    Some(to_duration(usage.ru_utime) + to_duration(usage.ru_stime))
+}
+
+#[cfg(not(unix))]
+fn cpu_time() -> Option<Duration> {
+    None
+}
+
+fn cpu_usage(since: Option<Duration>, elapsed: Duration) -> String {
+    match (since, cpu_time()) {
+        (Some(before), Some(after)) => format!(
+            "{:.2}% CPU",
+            (after - before).as_secs_f64() / elapsed.as_secs_f64() * 100.0
+        ),
+
+        _ => "CPU usage unavailable".to_owned(),
+    }
+}
+
+fn idle(addr: SocketAddr) {
+    let clients = (0..IDLE_CLIENTS)
+        .map(|n| Connection::open(addr, n).unwrap())
+        .collect::<Vec<_>>();
+
+    let before = cpu_time();
+    let start = Instant::now();
+
+    sleep(IDLE_DURATION);
+
+    println!(
+        "idle:       {} clients for {:?}: {}",
+        clients.len(),
+        IDLE_DURATION,
+        cpu_usage(before, start.elapsed())
+    );
+}
+
+fn throughput(addr: SocketAddr) {
+    let mut receiver = Connection::open(addr, IDLE_CLIENTS).unwrap();
+    receiver.join(CHANNEL).unwrap();
+
+    let barrier = Arc::new(Barrier::new(SENDERS + 1));
+
+    for i in 0..SENDERS {
+        let barrier = barrier.clone();
+        let mut sender = Connection::open(addr, IDLE_CLIENTS + 1 + i).unwrap();
+        sender.join(CHANNEL).unwrap();
+
+        let mut drain = sender.stream.try_clone().unwrap();
+
+        spawn(move || io::copy(&mut drain, &mut io::sink()));
+
+        spawn(move || {
+            barrier.wait();
+
+            for n in 0..MESSAGES_PER_SENDER {
+                let text = UserMessage::Text {
+                    channel: CHANNEL.to_owned(),
+                    message: format!("message {}", n),
+                };
+
+                Message::send(&mut sender.stream, text).unwrap();
+            }
+        });
+    }
+
+    barrier.wait();
+
+    let before = cpu_time();
+    let start = Instant::now();
+    let mut received = 0;
+
+    while received < SENDERS * MESSAGES_PER_SENDER {
+        if let Message::Server(ServerMessage::Recorded { .. }) = receiver.recv().unwrap() {
+            received += 1;
+        }
+    }
+
+    let elapsed = start.elapsed();
+
+    println!(
+        "throughput: {} messages from {} senders in {:?}: {:.0} messages/s, {}",
+        received,
+        SENDERS,
+        elapsed,
+        received as f64 / elapsed.as_secs_f64(),
+        cpu_usage(before, elapsed)
+    );
+}
+
+fn main() {
+    let args = Args::from_iter(&["lvchat-server", "--quiet", "--port", "0", "--rate", "0"]);
+    let config = Config::load(args).unwrap();
+
+    let reactor = Reactor::bind(State::new(config).unwrap()).unwrap();
+    let addr = SocketAddr::from(([127, 0, 0, 1], reactor.local_addrs().unwrap()[0].port()));
+
+    spawn(move || reactor.run());
+
+    idle(addr);
+    throughput(addr);
+}
@@ -0,0 +1,151 @@
+//! Registered accounts, each owning a nick protected by a password.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::PathBuf,
+};
+
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Account {
+    /// Salted argon2 hash in PHC string format.
+    pub hash: String,
+    pub created: DateTime<Utc>,
+}
+
+/// Accounts by nick, written to the file given by `--accounts` on every change.
+///
+/// Kept in memory only if no file is configured.
+#[derive(Debug)]
+pub struct Accounts {
+    path: Option<PathBuf>,
+    accounts: Mutex<HashMap<String, Account>>,
+}
+
+impl Accounts {
+    pub fn open(config: &Config) -> io::Result<Self> {
+        let accounts = match config.accounts_path {
+            Some(ref path) if path.exists() => {
+                serde_json::from_reader(BufReader::new(File::open(path)?))?
+            }
+
+            _ => HashMap::new(),
+        };
+
+        Ok(Accounts {
+            path: config.accounts_path.clone(),
+            accounts: Mutex::new(accounts),
+        })
+    }
+
+    pub fn is_registered(&self, nick: &str) -> bool {
+        self.accounts.lock().contains_key(nick)
+    }
+
+    /// Salted argon2 hash of `password`, which takes long enough to keep it off the reactor.
+    pub fn hash(password: &str) -> io::Result<String> {
+        let salt = SaltString::encode_b64(&rand::random::<[u8; 16]>())
+            .map_err(|e| io::Error::other(e.to_string()))?;
+
+        Ok(Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|e| io::Error::other(e.to_string()))?
+            .to_string())
+    }
+
+    /// Creates an account for `nick` with the `hash` of its password, returning `false` if it is
+    /// already registered.
+    pub fn register(&self, nick: &str, hash: String) -> io::Result<bool> {
+        let mut accounts = self.accounts.lock();
+
+        if accounts.contains_key(nick) {
+            return Ok(false);
+        }
+
+        accounts.insert(
+            nick.to_owned(),
+            Account {
+                hash,
+                created: Utc::now(),
+            },
+        );
+
+        if let Err(e) = self.persist(&accounts) {
+            accounts.remove(nick);
+
+            return Err(e);
+        }
+
+        Ok(true)
+    }
+
+    /// Whether `password` belongs to the account of `nick`. As slow as hashing it.
+    pub fn verify(&self, nick: &str, password: &str) -> bool {
+        let hash = match self.accounts.lock().get(nick) {
+            Some(account) => account.hash.clone(),
+            None => return false,
+        };
+
+        PasswordHash::new(&hash).is_ok_and(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+    }
+
+    fn persist(&self, accounts: &HashMap<String, Account>) -> io::Result<()> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+
+        // replace the file at once, so a crash never leaves half the accounts behind
+        let temporary = path.with_extension("tmp");
+
+        {
+            let mut writer = BufWriter::new(File::create(&temporary)?);
+
+            serde_json::to_writer_pretty(&mut writer, accounts)?;
+            writer.flush()?;
+        }
+
+        fs::rename(&temporary, path)
+    }
+}
+
+#[test]
+fn register_and_verify() {
+    use structopt::StructOpt;
+
+    use crate::config::Args;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("accounts.json");
+    let args = Args::from_iter(&["lvchat-server", "--accounts", path.to_str().unwrap()]);
+    let config = Config::load(args).unwrap();
+
+    let accounts = Accounts::open(&config).unwrap();
+
+    let hash = |password| Accounts::hash(password).unwrap();
+
+    assert!(accounts.register("alice", hash("hunter2")).unwrap());
+    assert!(!accounts.register("alice", hash("other")).unwrap());
+
+    let accounts = Accounts::open(&config).unwrap();
+
+    assert!(accounts.is_registered("alice"));
+    assert!(accounts.verify("alice", "hunter2"));
+    assert!(!accounts.verify("alice", "hunter3"));
+    assert!(!accounts.verify("bob", "hunter2"));
+}
@@ -0,0 +1,153 @@
+//! Address bans set by operators, written to the file given by `--bans`.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    net::IpAddr,
+    path::PathBuf,
+};
+
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ban {
+    /// Address pattern, `*` matching any run of characters.
+    pub mask: String,
+    pub reason: Option<String>,
+
+    /// Operator who set the ban.
+    pub by: String,
+
+    /// Expiry, `None` for a permanent ban.
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl Ban {
+    pub fn matches(&self, addr: &IpAddr) -> bool {
+        glob_match(&self.mask, &addr.to_string())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.until.is_some_and(|until| until <= Utc::now())
+    }
+}
+
+/// Kept in memory only if no file is configured.
+#[derive(Debug)]
+pub struct Bans {
+    path: Option<PathBuf>,
+    bans: Mutex<Vec<Ban>>,
+}
+
+impl Bans {
+    pub fn open(config: &Config) -> io::Result<Self> {
+        let bans = match config.bans_path {
+            Some(ref path) if path.exists() => {
+                serde_json::from_reader(BufReader::new(File::open(path)?))?
+            }
+
+            _ => vec![],
+        };
+
+        Ok(Bans {
+            path: config.bans_path.clone(),
+            bans: Mutex::new(bans),
+        })
+    }
+
+    /// Returns the ban keeping `addr` out, if any.
+    pub fn find(&self, addr: &IpAddr) -> Option<Ban> {
+        self.bans
+            .lock()
+            .iter()
+            .find(|ban| !ban.is_expired() && ban.matches(addr))
+            .cloned()
+    }
+
+    /// Adds `ban`, replacing an earlier one with the same mask.
+    pub fn add(&self, ban: Ban) -> io::Result<()> {
+        let mut bans = self.bans.lock();
+
+        bans.retain(|other| other.mask != ban.mask && !other.is_expired());
+        bans.push(ban);
+
+        self.persist(&bans)
+    }
+
+    fn persist(&self, bans: &[Ban]) -> io::Result<()> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+
+        let temporary = path.with_extension("tmp");
+
+        {
+            let mut writer = BufWriter::new(File::create(&temporary)?);
+
+            serde_json::to_writer_pretty(&mut writer, bans)?;
+            writer.flush()?;
+        }
+
+        fs::rename(&temporary, path)
+    }
+}
+
+/// Matches `text` against `pattern`, where `*` stands for any run of characters.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+
+    let first = parts.next().unwrap_or_default();
+
+    if !text.starts_with(first) {
+        return false;
+    }
+
+    let mut rest = &text[first.len()..];
+    let parts = parts.collect::<Vec<_>>();
+
+    match parts.split_last() {
+        // no wildcard at all
+        None => rest.is_empty(),
+
+        Some((last, middle)) => {
+            for part in middle {
+                match rest.find(part) {
+                    Some(pos) => rest = &rest[pos + part.len()..],
+                    None => return false,
+                }
+            }
+
+            rest.len() >= last.len() && rest.ends_with(last)
+        }
+    }
+}
+
+#[test]
+fn masks_and_expiry() {
+    let addr = "10.0.3.7".parse().unwrap();
+
+    assert!(glob_match("10.0.3.7", "10.0.3.7"));
+    assert!(!glob_match("10.0.3.7", "10.0.3.70"));
+    assert!(glob_match("10.0.*", "10.0.3.7"));
+    assert!(glob_match("*.7", "10.0.3.7"));
+    assert!(glob_match("10.*.7", "10.0.3.7"));
+    assert!(!glob_match("10.1.*", "10.0.3.7"));
+
+    let mut ban = Ban {
+        mask: "10.0.*".to_owned(),
+        reason: None,
+        by: "op".to_owned(),
+        until: Some(Utc::now() - chrono::Duration::seconds(1)),
+    };
+
+    assert!(ban.matches(&addr));
+    assert!(ban.is_expired());
+
+    ban.until = None;
+    assert!(!ban.is_expired());
+}
@@ -0,0 +1,27 @@
+use crate::client::Client;
+
+#[derive(Debug, Clone)]
+pub struct Channel {
+    pub name: String,
+    pub members: Vec<Client>,
+}
+
+impl Channel {
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        Channel {
+            name: name.into(),
+            members: vec![],
+        }
+    }
+
+    pub fn is_member(&self, client: &Client) -> bool {
+        self.members.contains(client)
+    }
+
+    pub fn nicks(&self) -> Vec<String> {
+        self.members
+            .iter()
+            .filter_map(|client| client.user.read().nick().map(ToOwned::to_owned))
+            .collect()
+    }
+}
@@ -1,31 +1,151 @@
-use std::{net::TcpStream, sync::Arc};
+use std::{
+    io::{self, ErrorKind, Write},
+    sync::Arc,
+    time::Instant,
+};
 
+use mio::Token;
 use parking_lot::{Mutex, RwLock};
 
-use lvchat_core::User;
+use lvchat_core::{FrameDecoder, Hello, Message, Peer, User};
+
+use crate::{config::Config, limiter::TokenBucket, outbound::OutboundQueue, stream::Stream};
 
 #[derive(Debug, Clone)]
 pub struct Client {
-    pub stream: Arc<Mutex<TcpStream>>,
+    pub token: Token,
+    pub stream: Arc<Mutex<Stream>>,
     pub user: Arc<RwLock<User>>,
     pub active: Arc<RwLock<bool>>,
+
+    /// Greeting of the client, set once its protocol revision was accepted.
+    pub hello: Arc<RwLock<Option<Hello>>>,
+
+    pub decoder: Arc<Mutex<FrameDecoder>>,
+
+    /// Encoded frames the socket didn't accept yet, written out as it becomes writable.
+    pub outbound: Arc<Mutex<OutboundQueue>>,
+
+    /// Last time anything was received from the client.
+    pub last_seen: Arc<RwLock<Instant>>,
+
+    /// Token of the session the client is attached to.
+    pub session: Arc<RwLock<Option<String>>>,
+
+    /// Whether the client may kick, ban and mute.
+    pub operator: Arc<RwLock<bool>>,
+
+    /// Limits how fast the client may send, unless rate limiting is disabled.
+    pub limiter: Arc<Mutex<TokenBucket>>,
+
+    /// Messages dropped for being rate limited since the client connected.
+    pub throttled: Arc<RwLock<u64>>,
+
+    /// Set while the worker checks or hashes a password of the client, which has to wait for
+    /// the result before authenticating again.
+    pub checking_password: Arc<RwLock<bool>>,
 }
 
 impl Client {
-    pub fn new(stream: TcpStream) -> Self {
-        let addr = stream.peer_addr().unwrap();
-
+    pub fn new(stream: Stream, peer: Peer, token: Token, config: &Config) -> Self {
         Client {
+            token,
             stream: Arc::new(Mutex::new(stream)),
-            user: Arc::new(RwLock::new(User::Ghost { addr })),
+            user: Arc::new(RwLock::new(User::Ghost { peer })),
             active: Arc::new(RwLock::new(true)),
+            hello: Arc::new(RwLock::new(None)),
+            decoder: Arc::new(Mutex::new(FrameDecoder::default())),
+            outbound: Arc::new(Mutex::new(OutboundQueue::new(
+                config.send_queue,
+                config.overflow,
+            ))),
+            last_seen: Arc::new(RwLock::new(Instant::now())),
+            session: Arc::new(RwLock::new(None)),
+            operator: Arc::new(RwLock::new(false)),
+            limiter: Arc::new(Mutex::new(TokenBucket::new(Instant::now()))),
+            throttled: Arc::new(RwLock::new(0)),
+            checking_password: Arc::new(RwLock::new(false)),
+        }
+    }
+}
+
+impl Client {
+    /// Whether the client announced `capability` in its greeting.
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.hello
+            .read()
+            .as_ref()
+            .is_some_and(|hello| hello.has_capability(capability))
+    }
+
+    /// Whether `message` reaches the client as is, without being translated for its revision.
+    pub fn understands(&self, message: &Message) -> bool {
+        self.hello
+            .read()
+            .as_ref()
+            .is_none_or(|hello| hello.understands(message))
+    }
+
+    /// Queues a message and writes as much of the queue as the socket accepts right now, in the
+    /// form the client's protocol revision knows, if any.
+    ///
+    /// Deactivates the client if its queue is full and the overflow policy is to disconnect.
+    pub fn send<M: Into<Message>>(&self, message: M) -> io::Result<()> {
+        let message = message.into();
+
+        // a client of an older revision would fail to decode what was added since
+        let message = match &*self.hello.read() {
+            Some(hello) if !hello.understands(&message) => {
+                match message.clone().for_revision(hello.protocol_version) {
+                    Some(message) => message,
+
+                    None => {
+                        log::debug!(
+                            "[Client: {}] Not sending what its revision lacks: {:?}",
+                            self,
+                            message
+                        );
+
+                        return Ok(());
+                    }
+                }
+            }
+
+            _ => message,
+        };
+
+        let frame = message.to_frame()?;
+
+        if let Err(e) = self.outbound.lock().push(frame) {
+            log::warn!("[Client: {}] Not reading fast enough, disconnecting", self);
+
+            *self.active.write() = false;
+            return Err(e);
+        }
+
+        self.flush()
+    }
+
+    /// Writes queued frames until the socket would block.
+    ///
+    /// The reactor calls this again once the socket becomes writable.
+    pub fn flush(&self) -> io::Result<()> {
+        let mut outbound = self.outbound.lock();
+        let mut stream = self.stream.lock();
+
+        outbound.write_to(&mut *stream)?;
+
+        // pushes out encrypted records still buffered by a TLS stream
+        match stream.flush() {
+            Err(e) if e.kind() != ErrorKind::WouldBlock => Err(e),
+            _ => Ok(()),
         }
     }
 }
 
 impl PartialEq<Self> for Client {
     fn eq(&self, other: &Client) -> bool {
-        *self.user.read() == *other.user.read()
+        self.token == other.token
     }
 }
 
@@ -1,39 +1,631 @@
-use std::path::PathBuf;
+//! Server configuration, read from an optional TOML file given by `--config` and overridden by
+//! command line flags.
+//!
+//! ```toml
+//! name = "lvchat"
+//! listen = ["0.0.0.0:5050", "[::]:5050", "tls://0.0.0.0:5443", "unix:lvchat.sock"]
+//! motd = "motd.txt"
+//! bans = "bans.json"
+//! channels = ["#lobby"]
+//!
+//! [logging]
+//! verbose = true
+//! path = "logs"
+//!
+//! [tls]
+//! cert = "cert.pem"
+//! key = "key.pem"
+//!
+//! [accounts]
+//! path = "accounts.json"
+//! no-guests = false
+//!
+//! [operators]
+//! nicks = ["admin"]
+//! password = "secret"
+//!
+//! [limits]
+//! rate = 5.0
+//! burst = 20
+//! flood-limit = 50
+//! send-queue = 1024
+//! overflow = "drop-oldest"
+//! keepalive = 30
+//! client-timeout = 90
+//! session-grace = 300
+//! shutdown-timeout = 5
+//!
+//! [history]
+//! path = "history.log"
+//! retention = 30
+//! replay = 50
+//! ```
+//!
+//! Without `listen`, the server listens on all IPv4 interfaces on `port` (5050 by default), over
+//! TLS if a certificate is configured.
+//!
+//! Relative paths in the file are resolved against the directory the file is in. SIGHUP reads
+//! the file again; listening, logging and storage settings only change on restart.
 
+use std::{
+    fs,
+    net::{Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::Deserialize;
 use structopt::StructOpt;
 
-#[derive(Debug, StructOpt)]
-pub struct Config {
-    #[structopt(short, long)]
+use lvchat_core::message::{is_valid_channel_name, Password};
+
+use crate::{error::Error, limiter::WARN_AFTER, listener::Listen, outbound::OverflowPolicy};
+
+/// Command line flags, each taking precedence over the configuration file.
+#[derive(Debug, Clone, Default, StructOpt)]
+pub struct Args {
+    /// TOML file to read the configuration from.
+    #[structopt(short, long = "config")]
+    pub config_path: Option<PathBuf>,
+
+    #[structopt(short, long, overrides_with = "no-verbose")]
     pub verbose: bool,
 
-    #[structopt(short, long)]
+    /// Turns off `verbose` of the configuration file.
+    #[structopt(long, overrides_with = "verbose")]
+    pub no_verbose: bool,
+
+    #[structopt(short, long, overrides_with = "no-debug")]
     pub debug: bool,
 
-    #[structopt(short, long)]
+    /// Turns off `debug` of the configuration file.
+    #[structopt(long, overrides_with = "debug")]
+    pub no_debug: bool,
+
+    #[structopt(short, long, overrides_with = "no-quiet")]
     pub quiet: bool,
 
-    #[structopt(short, long, default_value = "5050")]
-    pub port: u16,
+    /// Turns off `quiet` of the configuration file.
+    #[structopt(long, overrides_with = "quiet")]
+    pub no_quiet: bool,
+
+    /// Address to listen on, like `127.0.0.1:5050`, `[::]:5050`, `tls://0.0.0.0:5443` or
+    /// `unix:/run/lvchat.sock`. May be repeated. Defaults to all IPv4 interfaces on `--port`.
+    #[structopt(short, long)]
+    pub listen: Vec<Listen>,
+
+    /// Port to listen on if no `--listen` is given. Defaults to 5050.
+    #[structopt(short, long)]
+    pub port: Option<u16>,
 
     #[structopt(long = "logs")]
     pub logs_path: Option<PathBuf>,
-    //#[structopt(long)]
-    //pub client_timeout
+
+    /// PEM encoded certificate chain. Enables TLS together with `--key`.
+    #[structopt(long = "cert")]
+    pub cert_path: Option<PathBuf>,
+
+    /// PEM encoded private key of the certificate.
+    #[structopt(long = "key")]
+    pub key_path: Option<PathBuf>,
+
+    /// Name the server introduces itself with. Defaults to "lvchat".
+    #[structopt(long)]
+    pub name: Option<String>,
+
+    /// File holding the message of the day. Re-read on SIGHUP.
+    #[structopt(long = "motd")]
+    pub motd_path: Option<PathBuf>,
+
+    /// File registered accounts are stored in. Kept in memory only if omitted.
+    #[structopt(long = "accounts")]
+    pub accounts_path: Option<PathBuf>,
+
+    /// Only let clients with a registered account in.
+    #[structopt(long, overrides_with = "guests")]
+    pub no_guests: bool,
+
+    /// Lets guests in even if the configuration file has `no-guests` set.
+    #[structopt(long, overrides_with = "no-guests")]
+    pub guests: bool,
+
+    /// Registered accounts granted operator rights once authenticated. May be repeated.
+    #[structopt(long = "oper")]
+    pub opers: Vec<String>,
+
+    /// Password for becoming an operator with `Oper`. Disabled if omitted.
+    #[structopt(long = "oper-password", parse(from_str))]
+    pub oper_password: Option<Password>,
+
+    /// File bans are stored in. Kept in memory only if omitted.
+    #[structopt(long = "bans")]
+    pub bans_path: Option<PathBuf>,
+
+    /// Channel clients join once authenticated. May be repeated.
+    #[structopt(long = "channel")]
+    pub channels: Vec<String>,
+
+    /// Seconds of silence after which a client is pinged. 0 disables pings and timeouts.
+    /// Defaults to 30.
+    #[structopt(long)]
+    pub keepalive: Option<u64>,
+
+    /// Seconds of silence after which a client is dropped. Defaults to 90.
+    #[structopt(long = "client-timeout")]
+    pub client_timeout: Option<u64>,
+
+    /// Seconds a disconnected client may resume its session within. Defaults to 300.
+    #[structopt(long = "session-grace")]
+    pub session_grace: Option<u64>,
+
+    /// Seconds to wait for queued messages to reach clients when shutting down. Defaults to 5.
+    #[structopt(long = "shutdown-timeout")]
+    pub shutdown_timeout: Option<u64>,
+
+    /// Messages per second a client may send on average. 0 disables rate limiting.
+    /// Defaults to 5.
+    #[structopt(long)]
+    pub rate: Option<f64>,
+
+    /// Messages a client may send in a row before being rate limited. Defaults to 20.
+    #[structopt(long)]
+    pub burst: Option<u32>,
+
+    /// Messages dropped for being rate limited, forgiven one per ten seconds, after which the
+    /// client is disconnected. Defaults to 50.
+    #[structopt(long = "flood-limit")]
+    pub flood_limit: Option<u32>,
+
+    /// Frames queued for a client that doesn't read fast enough before `--overflow` applies.
+    /// Defaults to 1024.
+    #[structopt(long = "send-queue")]
+    pub send_queue: Option<usize>,
+
+    /// What to do with a client whose send queue is full: `drop-oldest` (default) or
+    /// `disconnect`.
+    #[structopt(long)]
+    pub overflow: Option<OverflowPolicy>,
+
+    /// File channel messages are recorded to. Kept in memory only if omitted.
+    #[structopt(long = "history")]
+    pub history_path: Option<PathBuf>,
+
+    /// Days after which recorded messages are dropped. 0 keeps them forever. Defaults to 30.
+    #[structopt(long = "history-retention")]
+    pub history_retention_days: Option<u32>,
+
+    /// Number of recorded messages replayed to clients joining a channel. Defaults to 50.
+    #[structopt(long = "history-replay")]
+    pub history_replay: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct File {
+    name: Option<String>,
+    listen: Option<Vec<Listen>>,
+    port: Option<u16>,
+    motd: Option<PathBuf>,
+    bans: Option<PathBuf>,
+    channels: Option<Vec<String>>,
+    logging: LoggingSection,
+    tls: TlsSection,
+    accounts: AccountsSection,
+    operators: OperatorsSection,
+    limits: LimitsSection,
+    history: HistorySection,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct LoggingSection {
+    verbose: Option<bool>,
+    debug: Option<bool>,
+    quiet: Option<bool>,
+    path: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct TlsSection {
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct AccountsSection {
+    path: Option<PathBuf>,
+    no_guests: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct OperatorsSection {
+    nicks: Option<Vec<String>>,
+    password: Option<Password>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct LimitsSection {
+    rate: Option<f64>,
+    burst: Option<u32>,
+    flood_limit: Option<u32>,
+    send_queue: Option<usize>,
+    overflow: Option<OverflowPolicy>,
+    keepalive: Option<u64>,
+    client_timeout: Option<u64>,
+    session_grace: Option<u64>,
+    shutdown_timeout: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct HistorySection {
+    path: Option<PathBuf>,
+    retention: Option<u32>,
+    replay: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub verbose: bool,
+    pub debug: bool,
+    pub quiet: bool,
+    pub listen: Vec<Listen>,
+    pub port: u16,
+    pub logs_path: Option<PathBuf>,
+    pub cert_path: Option<PathBuf>,
+    pub key_path: Option<PathBuf>,
+    pub name: String,
+    pub motd_path: Option<PathBuf>,
+    pub accounts_path: Option<PathBuf>,
+    pub no_guests: bool,
+    pub opers: Vec<String>,
+    pub oper_password: Option<Password>,
+    pub bans_path: Option<PathBuf>,
+    pub channels: Vec<String>,
+    pub keepalive: u64,
+    pub client_timeout: u64,
+    pub session_grace: u64,
+    pub shutdown_timeout: u64,
+    pub rate: f64,
+    pub burst: u32,
+    pub flood_limit: u32,
+    pub send_queue: usize,
+    pub overflow: OverflowPolicy,
+    pub history_path: Option<PathBuf>,
+    pub history_retention_days: u32,
+    pub history_replay: u32,
+
+    /// Flags the configuration was loaded with, applied again when reloading.
+    args: Args,
 }
 
 impl Config {
-    pub fn init() -> Self {
-        if cfg!(debug_assertions) {
-            Config {
-                verbose: true,
-                debug: true,
-                quiet: false,
-                port: 5050,
-                logs_path: None, //Some(PathBuf::from("logs")),
+    pub fn keepalive_interval(&self) -> Option<Duration> {
+        match self.keepalive {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    pub fn client_timeout(&self) -> Duration {
+        Duration::from_secs(self.client_timeout)
+    }
+
+    pub fn session_grace(&self) -> Duration {
+        Duration::from_secs(self.session_grace)
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout)
+    }
+}
+
+impl Config {
+    pub fn init() -> Result<Self, Error> {
+        Self::load(Args::from_args())
+    }
+
+    /// Reads the file given by `--config`, if any, and applies `args` on top of it.
+    pub fn load(args: Args) -> Result<Self, Error> {
+        let (file, base) = match args.config_path {
+            Some(ref path) => {
+                let content = fs::read_to_string(path).map_err(|e| {
+                    Error::Config(format!("Failed to read {}: {}", path.display(), e))
+                })?;
+
+                let file = toml::from_str::<File>(&content)
+                    .map_err(|e| Error::Config(format!("{}: {}", path.display(), e)))?;
+
+                let base = path.parent().map(Path::to_path_buf).unwrap_or_default();
+
+                (file, base)
+            }
+
+            None => (File::default(), PathBuf::new()),
+        };
+
+        let path = |path: Option<PathBuf>| path.map(|path| base.join(path));
+        let list = |list: &[String], file: Option<Vec<String>>| match list {
+            [] => file.unwrap_or_default(),
+            list => list.to_vec(),
+        };
+        // a switch given either way on the command line wins over the file
+        let switch =
+            |on: bool, off: bool, file: Option<bool>| on || (!off && file.unwrap_or(false));
+
+        let mut config = Config {
+            verbose: switch(args.verbose, args.no_verbose, file.logging.verbose),
+            debug: switch(args.debug, args.no_debug, file.logging.debug),
+            quiet: switch(args.quiet, args.no_quiet, file.logging.quiet),
+            listen: match args.listen.as_slice() {
+                [] => file
+                    .listen
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|listen| match listen {
+                        #[cfg(unix)]
+                        Listen::Unix { path } => Listen::Unix {
+                            path: base.join(path),
+                        },
+                        listen => listen,
+                    })
+                    .collect(),
+                listen => listen.to_vec(),
+            },
+            port: args.port.or(file.port).unwrap_or(5050),
+            logs_path: args.logs_path.clone().or(path(file.logging.path)),
+            cert_path: args.cert_path.clone().or(path(file.tls.cert)),
+            key_path: args.key_path.clone().or(path(file.tls.key)),
+            name: args
+                .name
+                .clone()
+                .or(file.name)
+                .unwrap_or_else(|| "lvchat".to_owned()),
+            motd_path: args.motd_path.clone().or(path(file.motd)),
+            accounts_path: args.accounts_path.clone().or(path(file.accounts.path)),
+            no_guests: switch(args.no_guests, args.guests, file.accounts.no_guests),
+            opers: list(&args.opers, file.operators.nicks),
+            oper_password: args.oper_password.clone().or(file.operators.password),
+            bans_path: args.bans_path.clone().or(path(file.bans)),
+            channels: list(&args.channels, file.channels),
+            keepalive: args.keepalive.or(file.limits.keepalive).unwrap_or(30),
+            client_timeout: args
+                .client_timeout
+                .or(file.limits.client_timeout)
+                .unwrap_or(90),
+            session_grace: args
+                .session_grace
+                .or(file.limits.session_grace)
+                .unwrap_or(300),
+            shutdown_timeout: args
+                .shutdown_timeout
+                .or(file.limits.shutdown_timeout)
+                .unwrap_or(5),
+            rate: args.rate.or(file.limits.rate).unwrap_or(5.0),
+            burst: args.burst.or(file.limits.burst).unwrap_or(20),
+            flood_limit: args.flood_limit.or(file.limits.flood_limit).unwrap_or(50),
+            send_queue: args.send_queue.or(file.limits.send_queue).unwrap_or(1024),
+            overflow: args
+                .overflow
+                .or(file.limits.overflow)
+                .unwrap_or(OverflowPolicy::DropOldest),
+            history_path: args.history_path.clone().or(path(file.history.path)),
+            history_retention_days: args
+                .history_retention_days
+                .or(file.history.retention)
+                .unwrap_or(30),
+            history_replay: args.history_replay.or(file.history.replay).unwrap_or(50),
+            args,
+        };
+
+        if config.listen.is_empty() {
+            config.listen.push(Listen::Tcp {
+                addr: SocketAddr::from((Ipv4Addr::UNSPECIFIED, config.port)),
+                tls: config.cert_path.is_some(),
+            });
+        }
+
+        config.validate().map_err(Error::Config)?;
+
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.cert_path.is_some() != self.key_path.is_some() {
+            return Err("A certificate and its key have to be given together".to_owned());
+        }
+
+        if let Some(listen) = self.listen.iter().find(|listen| listen.is_tls()) {
+            if self.cert_path.is_none() {
+                return Err(format!("Listening on {} requires a certificate", listen));
             }
-        } else {
-            <Self as StructOpt>::from_args()
         }
+
+        for (i, listen) in self.listen.iter().enumerate() {
+            if self.listen[..i].iter().any(|other| other.conflicts(listen)) {
+                return Err(format!("Listening on {} more than once", listen));
+            }
+        }
+
+        if self.name.trim().is_empty() {
+            return Err("Server name must not be empty".to_owned());
+        }
+
+        if !self.rate.is_finite() || self.rate < 0.0 {
+            return Err(format!("Rate must be a positive number, got {}", self.rate));
+        }
+
+        if self.burst == 0 {
+            return Err("Burst must be at least 1".to_owned());
+        }
+
+        if self.flood_limit <= WARN_AFTER {
+            return Err(format!(
+                "Flood limit must be greater than {}, got {}",
+                WARN_AFTER, self.flood_limit
+            ));
+        }
+
+        if self.send_queue == 0 {
+            return Err("Send queue must hold at least 1 message".to_owned());
+        }
+
+        if self.keepalive > 0 && self.client_timeout <= self.keepalive {
+            return Err(format!(
+                "Client timeout ({}s) must be longer than the keepalive interval ({}s)",
+                self.client_timeout, self.keepalive
+            ));
+        }
+
+        if let Some(channel) = self
+            .channels
+            .iter()
+            .find(|channel| !is_valid_channel_name(channel))
+        {
+            return Err(format!("Invalid channel name '{}'", channel));
+        }
+
+        if self.opers.iter().any(|oper| oper.trim().is_empty()) {
+            return Err("Operator nicks must not be empty".to_owned());
+        }
+
+        Ok(())
+    }
+
+    /// Reads the configuration file again, keeping the flags given on startup.
+    pub fn reload(&self) -> Result<Self, Error> {
+        Self::load(self.args.clone())
+    }
+
+    /// Takes the settings that only change on restart from `current`, returning the names of
+    /// those that differed.
+    pub fn keep_restart_settings(&mut self, current: &Config) -> Vec<&'static str> {
+        let mut changed = vec![];
+
+        macro_rules! keep {
+            ($($field:ident),*) => {
+                $(
+                    if self.$field != current.$field {
+                        self.$field = current.$field.clone();
+                        changed.push(stringify!($field));
+                    }
+                )*
+            };
+        }
+
+        keep!(
+            verbose,
+            debug,
+            quiet,
+            listen,
+            port,
+            logs_path,
+            cert_path,
+            key_path,
+            accounts_path,
+            bans_path,
+            history_path
+        );
+
+        changed
     }
 }
+
+#[test]
+fn file_and_flags() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("lvchat.toml");
+
+    fs::write(
+        &path,
+        "name = \"test\"\nport = 6000\nchannels = [\"#lobby\"]\n\n\
+         [limits]\nrate = 2.5\noverflow = \"disconnect\"\n\n[history]\npath = \"history.log\"\n",
+    )
+    .unwrap();
+
+    let args = Args::from_iter(&["lvchat-server", "-c", path.to_str().unwrap(), "-p", "7000"]);
+    let config = Config::load(args).unwrap();
+
+    assert_eq!(config.name, "test");
+    assert_eq!(config.port, 7000);
+    assert_eq!(config.listen, vec!["0.0.0.0:7000".parse().unwrap()]);
+    assert_eq!(config.rate, 2.5);
+    assert_eq!(config.overflow, OverflowPolicy::Disconnect);
+    assert_eq!(config.channels, vec!["#lobby".to_owned()]);
+    assert_eq!(config.history_path, Some(dir.path().join("history.log")));
+    assert_eq!(config.burst, 20);
+
+    fs::write(&path, "[limits]\nrate = 2.5\nburts = 10\n").unwrap();
+    assert!(config.reload().is_err());
+
+    fs::write(&path, "[limits]\nkeepalive = 120\n").unwrap();
+    assert!(config.reload().is_err());
+
+    fs::write(&path, "port = 6000\nname = \"renamed\"\n").unwrap();
+
+    let mut reloaded = config.reload().unwrap();
+
+    assert_eq!(
+        reloaded.keep_restart_settings(&config),
+        vec!["history_path"]
+    );
+    assert_eq!(reloaded.history_path, config.history_path);
+    assert_eq!(reloaded.name, "renamed");
+}
+
+#[test]
+fn flags_switch_off_file_settings() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("lvchat.toml");
+
+    fs::write(
+        &path,
+        "[logging]\nverbose = true\n\n[accounts]\nno-guests = true\n",
+    )
+    .unwrap();
+
+    let path = path.to_str().unwrap();
+
+    let config = Config::load(Args::from_iter(&["lvchat-server", "-c", path])).unwrap();
+    assert!(config.verbose && config.no_guests && !config.debug);
+
+    let args = Args::from_iter(&["lvchat-server", "-c", path, "--guests", "--no-verbose"]);
+    let config = Config::load(args).unwrap();
+    assert!(!config.verbose && !config.no_guests);
+
+    // the last of both ways wins
+    let args = Args::from_iter(&[
+        "lvchat-server",
+        "-c",
+        path,
+        "--guests",
+        "--no-guests",
+        "-dq",
+    ]);
+    let config = Config::load(args).unwrap();
+    assert!(config.no_guests && config.debug && config.quiet && config.verbose);
+}
+
+#[test]
+fn passwords_stay_out_of_debug_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("lvchat.toml");
+
+    fs::write(&path, "[operators]\npassword = \"from-file\"\n").unwrap();
+
+    let args = Args::from_iter(&["lvchat-server", "-c", path.to_str().unwrap()]);
+    let config = Config::load(args).unwrap();
+
+    assert_eq!(config.oper_password, Some("from-file".into()));
+    assert!(!format!("{:?}", config).contains("from-file"));
+
+    let args = Args::from_iter(&["lvchat-server", "--oper-password", "from-flag"]);
+    let config = Config::load(args).unwrap();
+
+    assert_eq!(config.oper_password, Some("from-flag".into()));
+    assert!(!format!("{:#?}", config).contains("from-flag"));
+}
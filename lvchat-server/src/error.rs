@@ -2,6 +2,7 @@
 #[derive(Debug)]
 pub enum Error {
     Io(std::io::Error),
+    Config(String),
 }
 
 impl std::error::Error for Error {}
@@ -10,6 +11,7 @@ impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             Self::Io(e) => write!(f, "I/O Error occurred: {}", e),
+            Self::Config(e) => write!(f, "Invalid configuration: {}", e),
         }
     }
 }
@@ -1,8 +1,59 @@
-use crate::client::Client;
+use std::{
+    io::{self, ErrorKind},
+    sync::Arc,
+};
 
-#[derive(Debug, Clone)]
+use flume::Sender;
+use mio::Waker;
+
+use lvchat_core::Hello;
+
+use crate::{client::Client, session::Session};
+
+#[derive(Debug)]
 pub enum Event {
-    Accepted(Client),
+    Accepted(Client, Hello),
     Authenticated(Client),
+
+    /// The client took over `session`, as it was before, with the channels to rejoin.
+    Resumed {
+        client: Client,
+        session: Session,
+    },
     Dropped(Client),
+
+    /// The password given for the registered `nick` was checked by the worker.
+    PasswordChecked {
+        client: Client,
+        nick: String,
+        valid: bool,
+    },
+
+    /// The password of a new account for `nick` was hashed by the worker.
+    PasswordHashed {
+        client: Client,
+        nick: String,
+        hash: io::Result<String>,
+    },
+}
+
+/// Hands events to the reactor, waking it up in case it is waiting for readiness.
+#[derive(Clone)]
+pub struct EventSender {
+    sender: Sender<Event>,
+    waker: Arc<Waker>,
+}
+
+impl EventSender {
+    pub fn new(sender: Sender<Event>, waker: Arc<Waker>) -> Self {
+        EventSender { sender, waker }
+    }
+
+    pub fn send(&self, event: Event) -> io::Result<()> {
+        self.sender
+            .send(event)
+            .map_err(|_| io::Error::new(ErrorKind::BrokenPipe, "Reactor is gone"))?;
+
+        self.waker.wake()
+    }
 }
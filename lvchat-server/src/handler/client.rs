@@ -1,129 +1,926 @@
 use std::{
-    io::{ErrorKind, Read, Write},
-    thread::yield_now,
+    io::{self, ErrorKind, Read},
+    time::Instant,
 };
 
-use flume::Sender;
+use chrono::{DateTime, TimeZone, Utc};
 
-use lvchat_core::*;
+use subtle::ConstantTimeEq;
 
-use crate::{client::Client, event::Event, state::State};
+use lvchat_core::{message::is_valid_channel_name, *};
 
-pub fn handle(state: State, client: Client, sender: Sender<Event>) {
-    let mut data = vec![];
-    let mut buffer = [0u8; 1024];
+use crate::{
+    accounts::Accounts,
+    bans::Ban,
+    client::Client,
+    event::{Event, EventSender},
+    history::{Before, Entry, HistoryStore},
+    limiter::{Metrics, WARN_AFTER},
+    session::Session,
+    state::State,
+};
 
-    log::trace!("Started client handler thread");
-    log::info!("[Client: {}] Connected.", client);
+/// Upper bound of messages sent in reply to a single history request.
+const MAX_HISTORY_PAGE: u32 = 200;
 
-    sender
-        .send(Event::Accepted(client.clone()))
-        .expect("Client accepted");
+/// Pages of missed messages sent per channel when a session is resumed.
+const MAX_RESUME_PAGES: u32 = 10;
 
-    'main: while *client.active.read() {
-        if let Some(mut client_stream) = client.stream.try_lock() {
-            match client_stream.read(&mut buffer) {
-                Ok(0) => (),
+/// Reads everything the client sent until its socket would block and handles the received
+/// messages.
+pub fn handle_readable(state: &State, client: &Client, sender: &EventSender) {
+    let mut buffer = [0u8; 4096];
 
-                Ok(size) => {
-                    data.extend_from_slice(&buffer[0..size]);
-                }
+    loop {
+        let read = client.stream.lock().read(&mut buffer);
 
-                Err(e) => match e.kind() {
-                    ErrorKind::ConnectionReset | ErrorKind::TimedOut => {
-                        log::warn!(
-                            "[Client: {}] Disconnected forcefully. Reason: {}",
-                            client,
-                            e
-                        );
+        match read {
+            Ok(0) => {
+                log::info!("[Client: {}] Disconnected.", client);
 
-                        break 'main;
-                    }
+                *client.active.write() = false;
+                break;
+            }
 
-                    ErrorKind::WouldBlock => (),
+            Ok(size) => {
+                client.decoder.lock().extend(&buffer[..size]);
 
-                    _ => {
-                        log::warn!("[Client: {}] Error was not processed: {}", client, e);
-                    }
-                },
+                *client.last_seen.write() = Instant::now();
             }
-        }
 
-        if data.is_empty() {
-            yield_now();
+            Err(e) => match e.kind() {
+                ErrorKind::WouldBlock => break,
+
+                ErrorKind::Interrupted => (),
+
+                _ => {
+                    log::warn!(
+                        "[Client: {}] Disconnected forcefully. Reason: {}",
+                        client,
+                        e
+                    );
+
+                    *client.active.write() = false;
+                    break;
+                }
+            },
         }
+    }
 
-        if let Some(eol) = data.windows(2).position(|w| w == b"\r\n") {
-            let raw = data.drain(..eol + 2).collect::<Vec<_>>();
-            let message = Message::from_bytes(&raw[..eol]);
+    loop {
+        let frame = client.decoder.lock().decode();
 
-            if let Some(message) = message {
-                log::info!("[Client: {}] Received message: {:#?}", client, message);
+        let frame = match frame {
+            Ok(Some(frame)) => frame,
 
-                handle_message(&state, &client, message, sender.clone());
+            Ok(None) => break,
 
-            // let _ = sender.send(Event::Message(stream.clone(), message));
-            } else {
-                log::warn!("Received invalid message. Skipping");
-                yield_now();
+            Err(e) => {
+                log::warn!("[Client: {}] Dropping connection: {}", client, e);
+
+                *client.active.write() = false;
+                break;
             }
+        };
+
+        if let Some(message) = Message::from_bytes(&frame) {
+            log::info!("[Client: {}] Received message: {:#?}", client, message);
+
+            handle_message(state, client, message, sender.clone());
+        } else {
+            log::warn!("Received invalid message. Skipping");
         }
     }
+}
 
-    sender.send(Event::Dropped(client)).expect("Client dropped");
+/// Continues writing queued frames once the client's socket accepts data again.
+pub fn handle_writable(client: &Client) {
+    if let Err(e) = client.flush() {
+        log::warn!("[Client: {}] Failed to write: {}", client, e);
 
-    log::trace!("Stopping client handler thread");
+        *client.active.write() = false;
+    }
 }
 
-fn broadcast_user_message(state: &State, client: &Client, message: &UserMessage) {
-    let refer = Message::Server(ServerMessage::Refer {
+fn refer(client: &Client, message: &UserMessage) -> Message {
+    Message::Server(ServerMessage::Refer {
         user: client.user.read().nick_unchecked().to_owned(),
         message: message.clone(),
+    })
+}
+
+fn broadcast_user_message(state: &State, client: &Client, message: &UserMessage) {
+    let refer = refer(client, message);
+
+    for client in get_all_clients_with_exception(state, &[client]) {
+        let _ = client.send(refer.clone());
+    }
+}
+
+fn broadcast_channel_message(state: &State, client: &Client, channel: &str, message: &UserMessage) {
+    let refer = refer(client, message);
+
+    // voice streams are large, so they only go to clients asking for them
+    let voice = matches!(message, UserMessage::Voice { .. });
+
+    if let Some(channel) = state.get_channel(channel) {
+        for member in channel.members.iter().filter(|member| *member != client) {
+            if !voice || member.has_capability(message::CAPABILITY_VOICE) {
+                let _ = member.send(refer.clone());
+            }
+        }
+    }
+}
+
+fn send_member_list(state: &State, client: &Client, channel: &str) {
+    let users = state
+        .get_channel(channel)
+        .map(|channel| channel.nicks())
+        .unwrap_or_default();
+
+    let _ = client.send(ServerMessage::MemberList {
+        channel: channel.to_owned(),
+        users,
+    });
+}
+
+fn send_history(state: &State, client: &Client, channel: &str, before: Option<Before>, limit: u32) {
+    let limit = limit.min(MAX_HISTORY_PAGE) as usize;
+
+    match state.history.fetch(channel, before, limit) {
+        Ok(entries) => {
+            let _ = client.send(ServerMessage::HistoryPage {
+                channel: channel.to_owned(),
+                messages: entries.into_iter().map(Into::into).collect(),
+            });
+        }
+
+        Err(e) => {
+            log::warn!("[Client: {}] Failed to look up history: {}", client, e);
+        }
+    }
+}
+
+fn record(state: &State, client: &Client, channel: &str, message: &str) -> Option<Entry> {
+    let entry = Entry::new(channel, client.user.read().nick_unchecked(), message);
+
+    match state.history.append(entry) {
+        Ok(entry) => Some(entry),
+
+        Err(e) => {
+            log::warn!("[Client: {}] Failed to record message: {}", client, e);
+            None
+        }
+    }
+}
+
+/// Relays recorded text to the other members of its channel and tells the sender its id.
+fn broadcast_recorded(state: &State, client: &Client, entry: Entry) {
+    let channel = entry.channel.clone();
+    let recorded = Message::Server(ServerMessage::Recorded {
+        channel: channel.clone(),
+        message: entry.into(),
+    });
+
+    if let Some(channel) = state.get_channel(&channel) {
+        for member in channel.members.iter().filter(|member| *member != client) {
+            let _ = member.send(recorded.clone());
+        }
+    }
+
+    // older revisions get no echo of their own text
+    if client.understands(&recorded) {
+        let _ = client.send(recorded);
+    }
+}
+
+/// Checks whether `nick` may be claimed, which a registered nick only may with a password.
+fn check_nick(state: &State, nick: &str, password: Option<&Password>) -> Result<(), ErrorMessage> {
+    if state.get_client_by_name(nick).is_some() || nick == "NOTICE" {
+        return Err(ErrorMessage::NickNameInUse);
+    }
+
+    if state.accounts.is_registered(nick) {
+        return match password {
+            None => Err(ErrorMessage::NickNameReserved),
+            Some(_) => Ok(()),
+        };
+    }
+
+    if state.get_session_by_nick(nick).is_some() {
+        Err(ErrorMessage::NickNameInUse)
+    } else if state.config().no_guests {
+        Err(ErrorMessage::AccountRequired)
+    } else {
+        Ok(())
+    }
+}
+
+/// Authenticates or renames the client as `nick` if it may claim it.
+///
+/// The password of a registered nick is checked by the worker, the claim is finished once the
+/// result arrives.
+fn claim_nick(
+    state: &State,
+    client: &Client,
+    nick: &str,
+    password: Option<&Password>,
+    sender: &EventSender,
+) {
+    if let Err(error) = check_nick(state, nick, password) {
+        let _ = client.send(error);
+        return;
+    }
+
+    let password = match password {
+        Some(password) if state.accounts.is_registered(nick) => password.clone(),
+        _ => return take_nick(state, client, nick, sender),
+    };
+
+    *client.checking_password.write() = true;
+
+    let accounts = state.accounts.clone();
+    let client = client.clone();
+    let nick = nick.to_owned();
+    let sender = sender.clone();
+
+    state.worker.run(move || {
+        let valid = accounts.verify(&nick, password.as_str());
+
+        // the reactor is gone if it shut down meanwhile
+        let _ = sender.send(Event::PasswordChecked {
+            client,
+            nick,
+            valid,
+        });
     });
+}
+
+/// Finishes claiming a registered nick once the worker checked the password.
+pub fn finish_claim(state: &State, client: &Client, nick: &str, valid: bool, sender: &EventSender) {
+    *client.checking_password.write() = false;
+
+    if !*client.active.read() {
+        return;
+    }
+
+    if !valid {
+        let _ = client.send(ErrorMessage::InvalidCredentials);
+        return;
+    }
+
+    // someone else may have been quicker meanwhile
+    if state.get_client_by_name(nick).is_some() {
+        let _ = client.send(ErrorMessage::NickNameInUse);
+        return;
+    }
+
+    take_nick(state, client, nick, sender);
+}
+
+/// Authenticates or renames the client as `nick`.
+///
+/// The owner of a registered nick takes it over from a detached session.
+fn take_nick(state: &State, client: &Client, nick: &str, sender: &EventSender) {
+    if let Some(session) = state.get_session_by_nick(nick) {
+        state.end_session(&session.token);
+    }
+
+    if client.user.read().is_ghost() {
+        authenticate(state, client, nick, sender);
+    } else {
+        rename(state, client, nick);
+    }
+}
+
+/// Tells everyone else about a new or changed nick, leaving out the password.
+fn announce_nick(state: &State, client: &Client, nick: &str) {
+    let auth = UserMessage::Auth {
+        nick: nick.to_owned(),
+        password: None,
+    };
+
+    broadcast_user_message(state, client, &auth);
+}
+
+fn authenticate(state: &State, client: &Client, nick: &str, sender: &EventSender) {
+    log::info!("[Client: {}] Now authenticated as {}", client, nick);
+
+    let user = client.user.read().clone();
+
+    *client.user.write() = User::Authenticated {
+        nick: nick.to_owned(),
+        peer: *user.peer(),
+    };
+
+    grant_configured_operator(state, client, nick);
+
+    let token = state.create_session(client);
+    let _ = client.send(ServerMessage::Session { token });
+
+    sender
+        .send(Event::Authenticated(client.clone()))
+        .expect("Client authenticated");
+
+    announce_nick(state, client, nick);
+}
+
+fn rename(state: &State, client: &Client, nick: &str) {
+    log::info!("[Client: {}] Changing nick to {}", client, nick);
+
+    if let Some(token) = client.session.read().as_deref() {
+        state.rename_session(token, nick);
+    }
+
+    grant_configured_operator(state, client, nick);
+
+    // others still know the client by its previous nick, and so does the client's confirmation
+    announce_nick(state, client, nick);
+
+    let _ = client.send(refer(
+        client,
+        &UserMessage::Auth {
+            nick: nick.to_owned(),
+            password: None,
+        },
+    ));
+
+    let mut user = client.user.write();
+
+    *user = User::Authenticated {
+        peer: *user.peer(),
+        nick: nick.to_owned(),
+    };
+}
+
+/// Makes the client an operator if `nick` is a registered account listed by `--oper`.
+///
+/// Only called once the client proved to own the nick.
+fn grant_configured_operator(state: &State, client: &Client, nick: &str) {
+    if state.config().opers.iter().any(|oper| oper == nick) && state.accounts.is_registered(nick) {
+        log::info!("[Client: {}] Is an operator", client);
+
+        *client.operator.write() = true;
+    }
+}
+
+fn millis(ts: Option<DateTime<Utc>>) -> Option<i64> {
+    ts.map(|ts| ts.timestamp_millis())
+}
+
+/// Tells the client off if it is muted, returning whether it is.
+fn reject_muted(state: &State, client: &Client) -> bool {
+    match state.muted_until(client) {
+        Some(until) => {
+            let _ = client.send(ErrorMessage::Muted {
+                until: millis(until),
+            });
+
+            true
+        }
+
+        None => false,
+    }
+}
+
+fn send_to_all(state: &State, message: Message) {
+    for client in state.clients.lock().iter() {
+        let _ = client.send(message.clone());
+    }
+}
+
+/// Disconnects the client for good, without a session to resume.
+fn disconnect(state: &State, client: &Client) {
+    if let Some(token) = client.session.write().take() {
+        state.end_session(&token);
+    }
+
+    *client.active.write() = false;
+}
+
+fn kick(state: &State, client: &Client, message: &UserMessage, nick: &str) {
+    let target = match state.get_client_by_name(nick) {
+        Some(target) => target,
+
+        None => {
+            let _ = client.send(ErrorMessage::NoSuchNick {
+                nick: nick.to_owned(),
+            });
+            return;
+        }
+    };
+
+    log::info!("[Client: {}] Kicked {}", client, target);
+
+    send_to_all(state, refer(client, message));
+    disconnect(state, &target);
+}
+
+fn ban(
+    state: &State,
+    client: &Client,
+    message: &UserMessage,
+    mask: &str,
+    duration: Option<u64>,
+    reason: &Option<String>,
+) {
+    let ban = Ban {
+        mask: mask.to_owned(),
+        reason: reason.clone(),
+        by: client.user.read().nick_unchecked().to_owned(),
+        until: duration.map(|secs| Utc::now() + chrono::Duration::seconds(secs as i64)),
+    };
+
+    log::info!("[Client: {}] Banned {}", client, mask);
+
+    if let Err(e) = state.bans.add(ban.clone()) {
+        log::warn!("[Client: {}] Failed to store ban: {}", client, e);
+    }
+
+    send_to_all(state, refer(client, message));
+
+    let targets = get_all_clients_with_exception(state, &[client])
+        .into_iter()
+        .filter(|target| {
+            target
+                .user
+                .read()
+                .peer()
+                .ip()
+                .is_some_and(|ip| ban.matches(&ip))
+        })
+        .collect::<Vec<_>>();
+
+    for target in targets {
+        let _ = target.send(ErrorMessage::Banned {
+            reason: ban.reason.clone(),
+            until: millis(ban.until),
+        });
+
+        if let Some(nick) = target.user.read().nick() {
+            let leave = UserMessage::Leave {
+                message: Some("Banned".to_owned()),
+            };
+
+            broadcast_user_message(state, &target, &leave);
+
+            log::info!("[Client: {}] Dropped by ban of {}", target, nick);
+        }
+
+        disconnect(state, &target);
+    }
+}
+
+fn mute(state: &State, client: &Client, message: &UserMessage, nick: &str, duration: Option<u64>) {
+    if !state.is_nick_taken(nick) {
+        let _ = client.send(ErrorMessage::NoSuchNick {
+            nick: nick.to_owned(),
+        });
+        return;
+    }
+
+    match duration {
+        Some(0) => {
+            log::info!("[Client: {}] Unmuted {}", client, nick);
+
+            state.unmute(nick);
+        }
+
+        duration => {
+            log::info!("[Client: {}] Muted {} for {:?}s", client, nick, duration);
+
+            let until = duration.map(|secs| Utc::now() + chrono::Duration::seconds(secs as i64));
+
+            state.mute(nick, until);
+        }
+    }
+
+    send_to_all(state, refer(client, message));
+}
+
+/// Makes the client an operator if it knows the password, unless its address is locked out.
+fn oper(state: &State, client: &Client, password: &Password) {
+    let ip = client.user.read().peer().ip();
+
+    if !state.may_oper(ip) {
+        log::warn!("[Client: {}] Locked out of operator logins", client);
+
+        let _ = client.send(ErrorMessage::InvalidCredentials);
+        return;
+    }
+
+    let valid = match state.config().oper_password {
+        Some(ref oper) => oper
+            .as_str()
+            .as_bytes()
+            .ct_eq(password.as_str().as_bytes())
+            .into(),
+        None => false,
+    };
+
+    if !valid {
+        log::warn!("[Client: {}] Failed to become an operator", client);
+
+        state.oper_failed(ip);
+
+        let _ = client.send(ErrorMessage::InvalidCredentials);
+        return;
+    }
+
+    log::info!("[Client: {}] Is now an operator", client);
+
+    *client.operator.write() = true;
+
+    let _ = client.send(ServerMessage::Notice {
+        message: "You are now an operator".to_owned(),
+    });
+}
+
+/// Creates an account for `nick` and claims it, unless someone else holds the nick.
+///
+/// The password is hashed by the worker, the registration is finished once the hash arrives.
+fn register(state: &State, client: &Client, nick: &str, password: &Password, sender: &EventSender) {
+    let own = client.user.read().nick() == Some(nick);
+
+    if !own && (state.is_nick_taken(nick) || nick == "NOTICE") {
+        let _ = client.send(ErrorMessage::NickNameInUse);
+        return;
+    }
+
+    if state.accounts.is_registered(nick) {
+        let _ = client.send(ErrorMessage::NickNameReserved);
+        return;
+    }
+
+    *client.checking_password.write() = true;
+
+    let client = client.clone();
+    let nick = nick.to_owned();
+    let password = password.clone();
+    let sender = sender.clone();
+
+    state.worker.run(move || {
+        let hash = Accounts::hash(password.as_str());
+
+        // the reactor is gone if it shut down meanwhile
+        let _ = sender.send(Event::PasswordHashed { client, nick, hash });
+    });
+}
+
+/// Finishes registering `nick` once the worker hashed the password.
+pub fn finish_register(
+    state: &State,
+    client: &Client,
+    nick: &str,
+    hash: io::Result<String>,
+    sender: &EventSender,
+) {
+    *client.checking_password.write() = false;
+
+    if !*client.active.read() {
+        return;
+    }
+
+    let own = client.user.read().nick() == Some(nick);
+
+    // someone else may have been quicker meanwhile
+    if !own && state.is_nick_taken(nick) {
+        let _ = client.send(ErrorMessage::NickNameInUse);
+        return;
+    }
+
+    match hash.and_then(|hash| state.accounts.register(nick, hash)) {
+        Ok(true) => {
+            log::info!("[Client: {}] Registered {}", client, nick);
+
+            let _ = client.send(ServerMessage::Notice {
+                message: format!("Registered {}", nick),
+            });
+
+            if client.user.read().is_ghost() {
+                authenticate(state, client, nick, sender);
+            } else if !own {
+                rename(state, client, nick);
+            }
+        }
+
+        Ok(false) => {
+            let _ = client.send(ErrorMessage::NickNameReserved);
+        }
+
+        Err(e) => {
+            log::warn!("[Client: {}] Failed to register {}: {}", client, nick, e);
+
+            let _ = client.send(ServerMessage::Notice {
+                message: "Registration failed".to_owned(),
+            });
+        }
+    }
+}
+
+/// Attaches the client to the session behind `token`, taking it over from any client still
+/// holding it, and has its channels and missed messages restored.
+fn resume(state: &State, client: &Client, token: &str, sender: &EventSender) {
+    let session = match state.get_session(token) {
+        Some(session) => session,
+
+        None => {
+            let _ = client.send(ErrorMessage::InvalidSession);
+            return;
+        }
+    };
+
+    let mut channels = session.channels.clone();
+
+    if let Some(previous) = session
+        .client
+        .and_then(|token| state.get_client_by_token(token))
+    {
+        log::info!("[Client: {}] Taking over session from {}", client, previous);
+
+        *previous.session.write() = None;
+        *previous.active.write() = false;
+
+        channels = state.part_all_channels(&previous);
+    }
+
+    let session = match state.attach_session(token, client) {
+        Some(session) => session,
+
+        None => {
+            let _ = client.send(ErrorMessage::InvalidSession);
+            return;
+        }
+    };
+
+    log::info!("[Client: {}] Resumed session of {}", client, session.nick);
+
+    let user = client.user.read().clone();
+
+    *client.user.write() = User::Authenticated {
+        nick: session.nick.clone(),
+        peer: *user.peer(),
+    };
+
+    let _ = client.send(ServerMessage::Resumed {
+        nick: session.nick.clone(),
+        channels: channels.clone(),
+    });
+
+    sender
+        .send(Event::Resumed {
+            client: client.clone(),
+            session: Session {
+                channels,
+                ..session
+            },
+        })
+        .expect("Client resumed");
+}
+
+/// Rejoins the channels of the session and catches the client up on what it missed while
+/// detached.
+///
+/// Unlike a fresh authentication, the client already saw the welcome and left the configured
+/// channels it wasn't in anymore.
+pub fn restore_session(state: &State, client: &Client, session: Session) {
+    for channel in &session.channels {
+        state.join_channel(channel, client);
+        send_member_list(state, client, channel);
+
+        // only what was said while detached, the client still has the rest
+        let since = session.detached_at.unwrap_or_else(Utc::now);
+
+        match missed_history(&*state.history, channel, since) {
+            Ok((pages, complete)) => {
+                if !complete {
+                    let _ = client.send(ServerMessage::Notice {
+                        message: format!(
+                            "Missed too many messages in {}, only showing the last {}.",
+                            channel,
+                            MAX_RESUME_PAGES * MAX_HISTORY_PAGE
+                        ),
+                    });
+                }
+
+                for page in pages.into_iter().rev() {
+                    let _ = client.send(ServerMessage::HistoryPage {
+                        channel: channel.clone(),
+                        messages: page.into_iter().map(Into::into).collect(),
+                    });
+                }
+            }
+
+            Err(e) => {
+                log::warn!("[Client: {}] Failed to look up history: {}", client, e);
+            }
+        }
+    }
+
+    for message in session.missed {
+        let _ = client.send(message);
+    }
+}
+
+/// Looks up what was said in `channel` after `since`, newest page first, and whether it all
+/// fit into `MAX_RESUME_PAGES`.
+///
+/// Returns a single empty page if nothing was missed.
+fn missed_history(
+    history: &dyn HistoryStore,
+    channel: &str,
+    since: DateTime<Utc>,
+) -> io::Result<(Vec<Vec<Entry>>, bool)> {
+    let mut pages = vec![];
+    let mut before = None;
+
+    let complete = loop {
+        let page = history.fetch(channel, before, MAX_HISTORY_PAGE as usize)?;
+
+        let exhausted =
+            page.len() < MAX_HISTORY_PAGE as usize || page.first().is_none_or(|e| e.ts <= since);
+        before = page.first().map(|entry| Before::Seq(entry.seq));
+
+        let missed = page
+            .into_iter()
+            .filter(|entry| entry.ts > since)
+            .collect::<Vec<_>>();
+
+        if !missed.is_empty() {
+            pages.push(missed);
+        }
+
+        if exhausted {
+            break true;
+        }
+
+        if pages.len() == MAX_RESUME_PAGES as usize {
+            break false;
+        }
+    };
+
+    if pages.is_empty() {
+        pages.push(vec![]);
+    }
+
+    Ok((pages, complete))
+}
+
+/// Adds the client to the channel, telling the members and catching the client up on it.
+pub fn join(state: &State, client: &Client, channel: &str) {
+    if !state.join_channel(channel, client) {
+        return;
+    }
+
+    log::info!("[Client: {}] Joined {}", client, channel);
+
+    let message = UserMessage::Join {
+        channel: channel.to_owned(),
+    };
+
+    broadcast_channel_message(state, client, channel, &message);
+    send_member_list(state, client, channel);
+    send_history(state, client, channel, None, state.config().history_replay);
+}
+
+fn is_member(state: &State, client: &Client, channel: &str) -> bool {
+    state
+        .get_channel(channel)
+        .is_some_and(|channel| channel.is_member(client))
+}
+
+/// Takes a token from the client's bucket, escalating from dropping the message to warning
+/// and finally disconnecting the client if it keeps flooding.
+fn is_throttled(state: &State, client: &Client) -> bool {
+    let config = state.config();
+
+    if config.rate <= 0.0 {
+        return false;
+    }
+
+    let (retry_after, violations) = {
+        let mut bucket = client.limiter.lock();
+
+        match bucket.acquire(config.rate, config.burst, Instant::now()) {
+            Ok(()) => return false,
+            Err(retry_after) => (retry_after, bucket.violations),
+        }
+    };
+
+    *client.throttled.write() += 1;
+    Metrics::count(&state.metrics.throttled);
+
+    let flood_limit = config.flood_limit;
+
+    // frames read along with the one exceeding the limit are dropped silently
+    if violations > flood_limit {
+        return true;
+    }
+
+    if violations == flood_limit {
+        log::warn!("[Client: {}] Disconnected for flooding", client);
+
+        Metrics::count(&state.metrics.disconnected);
+
+        if !client.user.read().is_ghost() {
+            let leave = UserMessage::Leave {
+                message: Some("Flooding".to_owned()),
+            };
+
+            broadcast_user_message(state, client, &leave);
+        }
+
+        disconnect(state, client);
+    } else if violations == WARN_AFTER {
+        log::debug!("[Client: {}] Rate limited", client);
+
+        Metrics::count(&state.metrics.warned);
+
+        let _ = client.send(ErrorMessage::RateLimited {
+            retry_after: retry_after.as_millis() as u64,
+        });
+    }
+
+    true
+}
+
+fn handle_message(state: &State, client: &Client, message: Message, sender: EventSender) {
+    // answers to our keepalive are free, so a throttled client isn't timed out
+    if let Message::User(UserMessage::Pong { .. }) = message {
+        return;
+    }
 
-    for client in get_all_clients_with_exception(&state, &[client]) {
-        let _ = client.stream.lock().write(&refer.to_bytes());
+    if is_throttled(state, client) {
+        return;
+    }
+
+    // keepalive works regardless of authentication
+    if let Message::User(UserMessage::Ping { token }) = message {
+        let _ = client.send(ServerMessage::Pong { token });
+        return;
     }
-}
 
-fn handle_message(state: &State, client: &Client, message: Message, sender: Sender<Event>) {
     if client.user.read().is_ghost() {
         match &message {
-            Message::User(message) => {
-                match message {
-                    UserMessage::Auth { nick } => {
-                        if state.get_client_by_name(&nick).is_some() || nick == "NOTICE" {
-                            let _ = Message::send(
-                                &mut *client.stream.lock(),
-                                ErrorMessage::NickNameInUse,
-                            );
-                        } else {
-                            log::info!("[Client: {}] Now authenticated as {}", client, nick);
-
-                            let user = client.user.read().clone();
-
-                            *client.user.write() = lvchat_core::User::Authenticated {
-                                nick: nick.clone(),
-                                addr: *user.addr(),
-                            };
+            Message::User(message) => match message {
+                UserMessage::Hello(hello) => {
+                    let mut greeting = client.hello.write();
 
-                            sender
-                                .send(Event::Authenticated(client.clone()))
-                                .expect("Client authenticated");
+                    if greeting.is_some() {
+                        log::warn!("[Client: {}] Sent a second greeting. Skipping.", client);
+                    } else {
+                        // recorded right away, as further frames of this read are handled before
+                        // the queued event
+                        if hello.is_compatible() {
+                            *greeting = Some(hello.clone());
                         }
-                    }
 
-                    _ => {
-                        log::info!(
-                            "[Client: {}] Sent message without being authenticated: {:#?}",
-                            client,
-                            message,
-                        );
+                        sender
+                            .send(Event::Accepted(client.clone(), hello.clone()))
+                            .expect("Client accepted");
                     }
                 }
 
-                broadcast_user_message(state, client, message);
-            }
+                UserMessage::Auth { .. }
+                | UserMessage::Resume { .. }
+                | UserMessage::Register { .. }
+                    if client.hello.read().is_none() =>
+                {
+                    log::warn!(
+                        "[Client: {}] Tried to authenticate before greeting. Skipping.",
+                        client
+                    );
+                }
+
+                UserMessage::Auth { .. }
+                | UserMessage::Resume { .. }
+                | UserMessage::Register { .. }
+                    if *client.checking_password.read() =>
+                {
+                    log::warn!(
+                        "[Client: {}] Tried to authenticate while its password is checked. Skipping.",
+                        client
+                    );
+                }
+
+                UserMessage::Auth { nick, password } => {
+                    claim_nick(state, client, nick, password.as_ref(), &sender)
+                }
+
+                UserMessage::Resume { token } => resume(state, client, token, &sender),
+
+                UserMessage::Register { nick, password } => {
+                    register(state, client, nick, password, &sender)
+                }
+
+                _ => {
+                    log::info!(
+                        "[Client: {}] Sent message without being authenticated: {:#?}",
+                        client,
+                        message,
+                    );
+                }
+            },
 
             _ => {
                 log::warn!(
@@ -140,47 +937,234 @@ fn handle_message(state: &State, client: &Client, message: Message, sender: Send
                 let mut broadcast = true;
 
                 match &message {
-                    UserMessage::Auth { nick } => {
-                        if state.get_client_by_name(&nick).is_some() {
-                            let _ = Message::send(
-                                &mut *client.stream.lock(),
-                                ErrorMessage::NickNameInUse,
-                            );
-                        } else {
-                            log::info!("[Client: {}] Changing nick to {}", client, nick);
+                    UserMessage::Hello(_) => {
+                        log::warn!("[Client: {}] Sent a second greeting. Skipping.", client);
+
+                        broadcast = false;
+                    }
 
-                            let mut user = client.user.write();
+                    // the mute stays with the client, so it mustn't leave it behind with its nick
+                    UserMessage::Auth { .. } if reject_muted(state, client) => {
+                        broadcast = false;
+                    }
 
-                            *user = User::Authenticated {
-                                addr: user.addr().clone(),
-                                nick: nick.clone(),
-                            };
-                        }
+                    UserMessage::Auth { .. } | UserMessage::Register { .. }
+                        if *client.checking_password.read() =>
+                    {
+                        log::warn!(
+                            "[Client: {}] Tried to change nick while its password is checked. Skipping.",
+                            client
+                        );
+
+                        broadcast = false;
+                    }
+
+                    UserMessage::Auth { nick, password } => {
+                        claim_nick(state, client, nick, password.as_ref(), &sender);
+
+                        broadcast = false;
+                    }
+
+                    UserMessage::Register { nick, password } => {
+                        register(state, client, nick, password, &sender);
+
+                        broadcast = false;
                     }
 
                     UserMessage::Leave { message } => {
                         log::info!("[Client: {}] Is leaving ({:?})", client, message);
 
+                        if let Some(token) = client.session.write().take() {
+                            state.end_session(&token);
+                        }
+
                         *client.active.write() = false;
                     }
 
                     UserMessage::RequestUserList => {
-                        let users = get_all_clients_with_exception(&state, &[client])
+                        let users = get_all_clients_with_exception(state, &[client])
                             .into_iter()
                             .filter_map(|client| client.user.read().nick().map(ToOwned::to_owned))
                             .collect::<Vec<_>>();
 
-                        let _ = Message::send(
-                            &mut *client.stream.lock(),
-                            ServerMessage::UserList { users },
+                        let _ = client.send(ServerMessage::UserList { users });
+
+                        broadcast = false;
+                    }
+
+                    UserMessage::Text { .. }
+                    | UserMessage::Voice { .. }
+                    | UserMessage::PrivateText { .. }
+                        if reject_muted(state, client) =>
+                    {
+                        broadcast = false;
+                    }
+
+                    UserMessage::Text { channel, .. } | UserMessage::Voice { channel, .. } => {
+                        if is_member(state, client, channel) {
+                            let recorded = match &message {
+                                UserMessage::Text { message: text, .. } => {
+                                    record(state, client, channel, text)
+                                }
+                                _ => None,
+                            };
+
+                            match recorded {
+                                Some(entry) => broadcast_recorded(state, client, entry),
+                                None => broadcast_channel_message(state, client, channel, &message),
+                            }
+                        } else {
+                            let _ = client.send(ErrorMessage::NotOnChannel {
+                                channel: channel.clone(),
+                            });
+                        }
+
+                        broadcast = false;
+                    }
+
+                    UserMessage::Join { channel } => {
+                        if !is_valid_channel_name(channel) {
+                            let _ = client.send(ErrorMessage::InvalidChannelName {
+                                channel: channel.clone(),
+                            });
+                        } else {
+                            join(state, client, channel);
+                        }
+
+                        broadcast = false;
+                    }
+
+                    UserMessage::Part {
+                        channel,
+                        message: reason,
+                    } => {
+                        if is_member(state, client, channel) {
+                            log::info!("[Client: {}] Parted {} ({:?})", client, channel, reason);
+
+                            broadcast_channel_message(state, client, channel, &message);
+                            state.part_channel(channel, client);
+                        } else {
+                            let _ = client.send(ErrorMessage::NotOnChannel {
+                                channel: channel.clone(),
+                            });
+                        }
+
+                        broadcast = false;
+                    }
+
+                    UserMessage::RequestMemberList { channel } => {
+                        send_member_list(state, client, channel);
+
+                        broadcast = false;
+                    }
+
+                    UserMessage::RequestHistory { channel, .. }
+                    | UserMessage::RequestHistoryPage { channel, .. }
+                        if !is_member(state, client, channel) =>
+                    {
+                        let _ = client.send(ErrorMessage::NotOnChannel {
+                            channel: channel.clone(),
+                        });
+
+                        broadcast = false;
+                    }
+
+                    UserMessage::RequestHistory {
+                        channel,
+                        before,
+                        limit,
+                    } => {
+                        let before = before
+                            .and_then(|before| Utc.timestamp_millis_opt(before).single())
+                            .map(Before::Time);
+
+                        send_history(state, client, channel, before, *limit);
+
+                        broadcast = false;
+                    }
+
+                    UserMessage::RequestHistoryPage {
+                        channel,
+                        before,
+                        limit,
+                    } => {
+                        send_history(state, client, channel, before.map(Before::Seq), *limit);
+
+                        broadcast = false;
+                    }
+
+                    UserMessage::Ping { .. } | UserMessage::Pong { .. } => {
+                        broadcast = false;
+                    }
+
+                    UserMessage::Oper { password } => {
+                        oper(state, client, password);
+
+                        broadcast = false;
+                    }
+
+                    UserMessage::Kick { .. }
+                    | UserMessage::Ban { .. }
+                    | UserMessage::Mute { .. }
+                        if !*client.operator.read() =>
+                    {
+                        let _ = client.send(ErrorMessage::NotOperator);
+
+                        broadcast = false;
+                    }
+
+                    UserMessage::Kick { nick, .. } => {
+                        kick(state, client, &message, nick);
+
+                        broadcast = false;
+                    }
+
+                    UserMessage::Ban {
+                        mask,
+                        duration,
+                        reason,
+                    } => {
+                        ban(state, client, &message, mask, *duration, reason);
+
+                        broadcast = false;
+                    }
+
+                    UserMessage::Mute { nick, duration } => {
+                        mute(state, client, &message, nick, *duration);
+
+                        broadcast = false;
+                    }
+
+                    UserMessage::Resume { .. } => {
+                        log::warn!(
+                            "[Client: {}] Tried to resume while authenticated. Skipping.",
+                            client
                         );
 
                         broadcast = false;
                     }
 
-                    UserMessage::Text { message: _ } => {}
+                    UserMessage::PrivateText { to, .. } => {
+                        match state.get_client_by_name(to) {
+                            Some(recipient) => {
+                                let _ = recipient.send(refer(client, &message));
+                            }
+
+                            None if state.queue_missed(to, refer(client, &message)) => {
+                                log::debug!(
+                                    "[Client: {}] Kept message for detached {}",
+                                    client,
+                                    to
+                                );
+                            }
+
+                            None => {
+                                let _ = client.send(ErrorMessage::NoSuchNick { nick: to.clone() });
+                            }
+                        }
 
-                    UserMessage::Voice { stream: _ } => {}
+                        broadcast = false;
+                    }
                 }
 
                 if broadcast {
@@ -213,3 +1197,628 @@ fn get_all_clients_with_exception(state: &State, excpetions: &[&Client]) -> Vec<
 
     clients
 }
+
+#[test]
+fn greet_only_once_per_connection() {
+    use std::{io::Write, net::TcpStream, thread::spawn};
+
+    use structopt::StructOpt;
+
+    use crate::{
+        config::{Args, Config},
+        reactor::Reactor,
+    };
+
+    let args = Args::from_iter(&["lvchat-server", "--quiet", "--port", "0"]);
+    let reactor = Reactor::bind(State::new(Config::load(args).unwrap()).unwrap()).unwrap();
+    let port = reactor.local_addrs().unwrap()[0].port();
+
+    spawn(move || reactor.run());
+
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+
+    let hello = Message::from(UserMessage::Hello(Hello::new("test")))
+        .to_frame()
+        .unwrap();
+    stream
+        .write_all(&[&hello[..], &hello[..]].concat())
+        .unwrap();
+
+    assert!(matches!(
+        Message::recv(&mut stream),
+        Ok(Message::Server(ServerMessage::Hello(_)))
+    ));
+    assert!(matches!(
+        Message::recv(&mut stream),
+        Ok(Message::Server(ServerMessage::Auth))
+    ));
+
+    Message::send(&mut stream, UserMessage::Ping { token: 7 }).unwrap();
+
+    assert!(matches!(
+        Message::recv(&mut stream),
+        Ok(Message::Server(ServerMessage::Pong { token: 7 }))
+    ));
+}
+
+#[test]
+fn page_back_to_detachment() {
+    use crate::history::MemoryHistory;
+
+    let history = MemoryHistory::default();
+    let start = Utc::now() - chrono::Duration::hours(1);
+
+    let entry = |n: i64| {
+        let mut entry = Entry::new("#lobby", "alice", n.to_string());
+        entry.ts = start + chrono::Duration::milliseconds(n);
+        entry
+    };
+
+    for n in 0..500 {
+        history.append(entry(n)).unwrap();
+    }
+
+    history
+        .append(Entry::new("#other", "bob", "elsewhere"))
+        .unwrap();
+
+    let since = start + chrono::Duration::milliseconds(49);
+    let (pages, complete) = missed_history(&history, "#lobby", since).unwrap();
+
+    assert!(complete);
+    assert_eq!(
+        pages.iter().map(Vec::len).collect::<Vec<_>>(),
+        [200, 200, 50]
+    );
+    assert_eq!(pages[2][0].message, "50");
+    assert_eq!(pages[0][199].message, "499");
+
+    let (pages, complete) = missed_history(&history, "#lobby", Utc::now()).unwrap();
+
+    assert!(complete);
+    assert_eq!(pages, [vec![]]);
+
+    for n in 500..2500 {
+        history.append(entry(n)).unwrap();
+    }
+
+    let (pages, complete) = missed_history(&history, "#lobby", since).unwrap();
+
+    assert!(!complete);
+    assert_eq!(pages.len(), MAX_RESUME_PAGES as usize);
+}
+
+#[test]
+fn charge_pings_to_the_bucket() {
+    use std::{io::Write, net::TcpStream, thread::spawn};
+
+    use structopt::StructOpt;
+
+    use crate::{
+        config::{Args, Config},
+        reactor::Reactor,
+    };
+
+    let args = Args::from_iter(&[
+        "lvchat-server",
+        "--quiet",
+        "--port",
+        "0",
+        "--rate",
+        "0.1",
+        "--burst",
+        "3",
+    ]);
+    let reactor = Reactor::bind(State::new(Config::load(args).unwrap()).unwrap()).unwrap();
+    let port = reactor.local_addrs().unwrap()[0].port();
+
+    spawn(move || reactor.run());
+
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+
+    let pings = (0..10)
+        .flat_map(|token| {
+            Message::from(UserMessage::Ping { token })
+                .to_frame()
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    stream.write_all(&pings).unwrap();
+
+    for token in 0..3 {
+        assert_eq!(
+            Message::recv(&mut stream).unwrap(),
+            Message::Server(ServerMessage::Pong { token })
+        );
+    }
+
+    assert!(matches!(
+        Message::recv(&mut stream),
+        Ok(Message::Error(ErrorMessage::RateLimited { .. }))
+    ));
+}
+
+#[test]
+fn keep_the_mute_across_renames() {
+    use std::{net::TcpStream, thread::spawn, time::Duration};
+
+    use structopt::StructOpt;
+
+    use crate::{
+        config::{Args, Config},
+        reactor::Reactor,
+    };
+
+    let args = Args::from_iter(&[
+        "lvchat-server",
+        "--quiet",
+        "--port",
+        "0",
+        "--oper-password",
+        "secret",
+    ]);
+    let reactor = Reactor::bind(State::new(Config::load(args).unwrap()).unwrap()).unwrap();
+    let port = reactor.local_addrs().unwrap()[0].port();
+
+    spawn(move || reactor.run());
+
+    // skips whatever else the server sends until the expected message
+    let expect = |stream: &mut TcpStream, expected: fn(&Message) -> bool| loop {
+        if expected(&Message::recv(stream).unwrap()) {
+            break;
+        }
+    };
+
+    let connect = |nick: &str| {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        Message::send(&mut stream, UserMessage::Hello(Hello::new("test"))).unwrap();
+        Message::send(
+            &mut stream,
+            UserMessage::Auth {
+                nick: nick.to_owned(),
+                password: None,
+            },
+        )
+        .unwrap();
+        expect(&mut stream, |message| {
+            matches!(message, Message::Server(ServerMessage::Session { .. }))
+        });
+
+        stream
+    };
+
+    let mut op = connect("op");
+    let mut bob = connect("bob");
+
+    Message::send(
+        &mut op,
+        UserMessage::Oper {
+            password: "secret".into(),
+        },
+    )
+    .unwrap();
+    expect(&mut op, |message| {
+        matches!(message, Message::Server(ServerMessage::Notice { .. }))
+    });
+
+    Message::send(
+        &mut op,
+        UserMessage::Mute {
+            nick: "bob".to_owned(),
+            duration: None,
+        },
+    )
+    .unwrap();
+    expect(&mut bob, |message| {
+        matches!(message, Message::Server(ServerMessage::Refer { .. }))
+    });
+
+    Message::send(
+        &mut bob,
+        UserMessage::Auth {
+            nick: "carol".to_owned(),
+            password: None,
+        },
+    )
+    .unwrap();
+    expect(&mut bob, |message| {
+        matches!(message, Message::Error(ErrorMessage::Muted { until: None }))
+    });
+
+    // nobody can take the nick and the mute with it, and the mute still holds
+    let mut carol = connect("carol");
+
+    Message::send(
+        &mut carol,
+        UserMessage::Auth {
+            nick: "bob".to_owned(),
+            password: None,
+        },
+    )
+    .unwrap();
+    expect(&mut carol, |message| {
+        matches!(message, Message::Error(ErrorMessage::NickNameInUse))
+    });
+
+    Message::send(
+        &mut bob,
+        UserMessage::PrivateText {
+            to: "carol".to_owned(),
+            message: "hi".to_owned(),
+        },
+    )
+    .unwrap();
+    expect(&mut bob, |message| {
+        matches!(message, Message::Error(ErrorMessage::Muted { until: None }))
+    });
+}
+
+#[test]
+fn check_passwords_off_the_reactor() {
+    use std::{io::Write, net::TcpStream, thread::spawn, time::Duration};
+
+    use structopt::StructOpt;
+
+    use crate::{
+        config::{Args, Config},
+        reactor::Reactor,
+    };
+
+    let args = Args::from_iter(&["lvchat-server", "--quiet", "--port", "0"]);
+    let reactor = Reactor::bind(State::new(Config::load(args).unwrap()).unwrap()).unwrap();
+    let port = reactor.local_addrs().unwrap()[0].port();
+
+    spawn(move || reactor.run());
+
+    let connect = || {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(30)))
+            .unwrap();
+
+        Message::send(&mut stream, UserMessage::Hello(Hello::new("test"))).unwrap();
+
+        assert!(matches!(
+            Message::recv(&mut stream),
+            Ok(Message::Server(ServerMessage::Hello(_)))
+        ));
+        assert!(matches!(
+            Message::recv(&mut stream),
+            Ok(Message::Server(ServerMessage::Auth))
+        ));
+
+        stream
+    };
+
+    let mut alice = connect();
+
+    Message::send(
+        &mut alice,
+        UserMessage::Register {
+            nick: "alice".to_owned(),
+            password: "hunter2".into(),
+        },
+    )
+    .unwrap();
+
+    assert!(matches!(
+        Message::recv(&mut alice),
+        Ok(Message::Server(ServerMessage::Notice { .. }))
+    ));
+    assert!(matches!(
+        Message::recv(&mut alice),
+        Ok(Message::Server(ServerMessage::Session { .. }))
+    ));
+
+    Message::send(&mut alice, UserMessage::Leave { message: None }).unwrap();
+
+    // gone once the server closed the connection
+    while Message::recv(&mut alice).is_ok() {}
+
+    // the reactor keeps serving while the password is checked
+    let mut mallory = connect();
+
+    let frames = [
+        UserMessage::Auth {
+            nick: "alice".to_owned(),
+            password: Some("hunter3".into()),
+        },
+        UserMessage::Ping { token: 7 },
+    ]
+    .iter()
+    .flat_map(|message| Message::from(message.clone()).to_frame().unwrap())
+    .collect::<Vec<_>>();
+    mallory.write_all(&frames).unwrap();
+
+    assert_eq!(
+        Message::recv(&mut mallory).unwrap(),
+        Message::Server(ServerMessage::Pong { token: 7 })
+    );
+    assert_eq!(
+        Message::recv(&mut mallory).unwrap(),
+        Message::Error(ErrorMessage::InvalidCredentials)
+    );
+}
+
+#[test]
+fn spare_older_revisions_what_they_lack() {
+    use std::{net::TcpStream, thread::spawn, time::Duration};
+
+    use structopt::StructOpt;
+
+    use crate::{
+        config::{Args, Config},
+        reactor::Reactor,
+    };
+
+    let args = Args::from_iter(&[
+        "lvchat-server",
+        "--quiet",
+        "--port",
+        "0",
+        "--oper-password",
+        "secret",
+    ]);
+    let reactor = Reactor::bind(State::new(Config::load(args).unwrap()).unwrap()).unwrap();
+    let port = reactor.local_addrs().unwrap()[0].port();
+
+    spawn(move || reactor.run());
+
+    // everything the server sends until the expected message
+    let until = |stream: &mut TcpStream, expected: fn(&Message) -> bool| {
+        let mut received = vec![];
+
+        loop {
+            let message = Message::recv(stream).unwrap();
+
+            if expected(&message) {
+                break received;
+            }
+
+            received.push(message);
+        }
+    };
+
+    let connect = |nick: &str, protocol_version: u32| {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        let mut hello = Hello::new("test");
+        hello.protocol_version = protocol_version;
+
+        Message::send(&mut stream, UserMessage::Hello(hello)).unwrap();
+        Message::send(
+            &mut stream,
+            UserMessage::Auth {
+                nick: nick.to_owned(),
+                password: None,
+            },
+        )
+        .unwrap();
+        until(&mut stream, |message| {
+            matches!(message, Message::Server(ServerMessage::Session { .. }))
+        });
+
+        stream
+    };
+
+    let mut op = connect("op", message::PROTOCOL_VERSION);
+    let mut bob = connect("bob", 3);
+
+    Message::send(
+        &mut op,
+        UserMessage::Oper {
+            password: "secret".into(),
+        },
+    )
+    .unwrap();
+    Message::send(
+        &mut op,
+        UserMessage::Mute {
+            nick: "bob".to_owned(),
+            duration: None,
+        },
+    )
+    .unwrap();
+    until(&mut op, |message| {
+        matches!(message, Message::Server(ServerMessage::Refer { .. }))
+    });
+
+    Message::send(
+        &mut bob,
+        UserMessage::PrivateText {
+            to: "op".to_owned(),
+            message: "hi".to_owned(),
+        },
+    )
+    .unwrap();
+    Message::send(&mut bob, UserMessage::Ping { token: 7 }).unwrap();
+
+    let received = until(&mut bob, |message| {
+        *message == Message::Server(ServerMessage::Pong { token: 7 })
+    });
+
+    assert!(received.iter().all(|message| message.revision() <= 3));
+
+    // recorded text tells its id to the sender, older revisions only get to read it
+    for stream in [&mut op, &mut bob] {
+        Message::send(
+            stream,
+            UserMessage::Join {
+                channel: "#lobby".to_owned(),
+            },
+        )
+        .unwrap();
+        until(stream, |message| {
+            matches!(message, Message::Server(ServerMessage::MemberList { .. }))
+        });
+    }
+
+    Message::send(
+        &mut op,
+        UserMessage::Text {
+            channel: "#lobby".to_owned(),
+            message: "hello".to_owned(),
+        },
+    )
+    .unwrap();
+
+    until(&mut op, |message| {
+        matches!(
+            message,
+            Message::Server(ServerMessage::Recorded { message, .. })
+                if message.id > 0 && message.user == "op"
+        )
+    });
+
+    let received = until(&mut bob, |message| {
+        matches!(
+            message,
+            Message::Server(ServerMessage::Refer {
+                message: UserMessage::Text { .. },
+                ..
+            })
+        )
+    });
+
+    assert!(received.iter().all(|message| message.revision() <= 3));
+}
+
+#[test]
+fn resume_only_what_the_session_had() {
+    use std::{net::TcpStream, thread::spawn, time::Duration};
+
+    use structopt::StructOpt;
+
+    use crate::{
+        config::{Args, Config},
+        reactor::Reactor,
+    };
+
+    let args = Args::from_iter(&[
+        "lvchat-server",
+        "--quiet",
+        "--port",
+        "0",
+        "--channel",
+        "#lobby",
+    ]);
+    let reactor = Reactor::bind(State::new(Config::load(args).unwrap()).unwrap()).unwrap();
+    let port = reactor.local_addrs().unwrap()[0].port();
+
+    spawn(move || reactor.run());
+
+    // everything the server sends until the expected message, which is returned last
+    let until = |stream: &mut TcpStream, expected: fn(&Message) -> bool| {
+        let mut received = vec![];
+
+        loop {
+            let message = Message::recv(stream).unwrap();
+            let done = expected(&message);
+
+            received.push(message);
+
+            if done {
+                break received;
+            }
+        }
+    };
+
+    let connect = || {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        Message::send(&mut stream, UserMessage::Hello(Hello::new("test"))).unwrap();
+
+        stream
+    };
+
+    let mut alice = connect();
+
+    Message::send(
+        &mut alice,
+        UserMessage::Auth {
+            nick: "alice".to_owned(),
+            password: None,
+        },
+    )
+    .unwrap();
+
+    let token = match until(&mut alice, |message| {
+        matches!(message, Message::Server(ServerMessage::Session { .. }))
+    })
+    .pop()
+    {
+        Some(Message::Server(ServerMessage::Session { token })) => token,
+        _ => unreachable!(),
+    };
+
+    Message::send(
+        &mut alice,
+        UserMessage::Join {
+            channel: "#other".to_owned(),
+        },
+    )
+    .unwrap();
+    Message::send(
+        &mut alice,
+        UserMessage::Part {
+            channel: "#lobby".to_owned(),
+            message: None,
+        },
+    )
+    .unwrap();
+    Message::send(&mut alice, UserMessage::Ping { token: 1 }).unwrap();
+    until(&mut alice, |message| {
+        matches!(message, Message::Server(ServerMessage::Pong { .. }))
+    });
+
+    drop(alice);
+
+    let mut alice = connect();
+
+    Message::send(&mut alice, UserMessage::Resume { token }).unwrap();
+
+    let resumed = until(&mut alice, |message| {
+        matches!(message, Message::Server(ServerMessage::Resumed { .. }))
+    })
+    .pop();
+
+    assert_eq!(
+        resumed,
+        Some(Message::Server(ServerMessage::Resumed {
+            nick: "alice".to_owned(),
+            channels: vec!["#other".to_owned()],
+        }))
+    );
+
+    // handled after the restoration queued along with the resumption
+    Message::send(&mut alice, UserMessage::Ping { token: 2 }).unwrap();
+
+    let restored = until(&mut alice, |message| {
+        matches!(message, Message::Server(ServerMessage::Pong { .. }))
+    });
+
+    assert!(restored.iter().any(|message| matches!(
+        message,
+        Message::Server(ServerMessage::MemberList { channel, .. }) if channel == "#other"
+    )));
+    assert!(!restored.iter().any(|message| matches!(
+        message,
+        Message::Server(ServerMessage::MemberList { channel, .. }) if channel == "#lobby"
+    )));
+    assert!(!restored.iter().any(|message| matches!(
+        message,
+        Message::Server(ServerMessage::Notice { .. })
+            | Message::Server(ServerMessage::MessageOfTheDay { .. })
+            | Message::Server(ServerMessage::UserList { .. })
+    )));
+}
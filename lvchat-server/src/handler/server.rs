@@ -1,64 +1,75 @@
-use std::{
-    io::ErrorKind,
-    net::TcpStream,
-    thread::spawn,
-};
+use std::io::Write;
 
-use flume::{Receiver, Sender, TryRecvError};
+use mio::{Interest, Registry, Token};
 
 use lvchat_core::*;
 
-use crate::{client::Client, event::Event, handler::client, state::State};
-
-pub fn handle(state: State, client_queue_rx: Receiver<TcpStream>) {
-    let (client_event_tx, client_event_rx) = flume::unbounded();
+use crate::{
+    client::Client,
+    event::{Event, EventSender},
+    listener::Listener,
+    state::State,
+    stream::Stream,
+    tls::TlsStream,
+};
 
-    log::trace!("Started server handling thread");
+const SOFTWARE: &str = concat!("lvchat-server ", env!("CARGO_PKG_VERSION"));
 
-    loop {
-        match client_queue_rx.try_recv() {
-            Ok(client) => {
-                handle_incoming_client(&state, client, &client_event_tx);
+pub fn handle_event(state: &State, event: Event, sender: &EventSender) {
+    match event {
+        Event::Accepted(client, hello) => {
+            if !hello.is_compatible() {
+                log::info!(
+                    "[Client: {}] Rejected: Incompatible protocol revision {} ({})",
+                    client,
+                    hello.protocol_version,
+                    hello.software
+                );
+
+                let _ = client.send(ErrorMessage::IncompatibleProtocol {
+                    min: message::MIN_PROTOCOL_VERSION,
+                    max: message::PROTOCOL_VERSION,
+                });
+
+                *client.active.write() = false;
+
+                return;
             }
 
-            Err(e) => match e {
-                TryRecvError::Disconnected => {
-                    eprintln!("We've been disconnected. Stopping..");
-                    break;
-                }
-
-                _ => (),
-            },
-        }
+            log::debug!(
+                "[Client: {}] Greeted by {} (protocol revision {})",
+                client,
+                hello.software,
+                hello.protocol_version
+            );
 
-        match client_event_rx.try_recv() {
-            Ok(event) => handle_event(&state, event),
-
-            Err(e) => match e {
-                TryRecvError::Disconnected => {
-                    eprintln!("We've been disconnected. Stopping..");
-                    break;
-                }
-
-                _ => (),
-            },
-        }
-    }
-}
+            let _ = client.send(ServerMessage::Hello(Hello::new(SOFTWARE)));
 
-fn handle_event(state: &State, event: Event) {
-    match event {
-        Event::Accepted(client) => {
             log::debug!("[Client: {}] Sending authentication request", client);
 
-            let _ = Message::send(&mut *client.stream.lock(), ServerMessage::Auth);
+            let _ = client.send(ServerMessage::Auth);
         }
         Event::Authenticated(client) => {
-            log::debug!("[Client: {}] Sending welcome notice", client);
+            match state.render_motd() {
+                Some(motd) => {
+                    log::debug!("[Client: {}] Sending message of the day", client);
+
+                    let _ = client.send(ServerMessage::MessageOfTheDay {
+                        message: Some(motd),
+                    });
+                }
 
-            let _ = Message::send(&mut *client.stream.lock(), ServerMessage::Notice { message: format!("Welcome!") });
+                None => {
+                    log::debug!("[Client: {}] Sending welcome notice", client);
+
+                    let _ = client.send(ServerMessage::Notice {
+                        message: format!("Welcome to {}!", state.config().name),
+                    });
+                }
+            }
 
-            let users = state.clients
+            let users = state
+                .clients
                 .lock()
                 .iter()
                 .filter_map(|client| client.user.read().nick().map(ToOwned::to_owned))
@@ -67,67 +78,128 @@ fn handle_event(state: &State, event: Event) {
 
             log::debug!("[Client: {}] Sending user list: {:#?}", client, users);
 
-            let _ = Message::send(&mut *client.stream.lock(), ServerMessage::UserList {
-                users,
-            });
+            let _ = client.send(ServerMessage::UserList { users });
+
+            for channel in &state.config().channels {
+                super::client::join(state, &client, channel);
+            }
+        }
+        Event::Resumed { client, session } => {
+            log::debug!("[Client: {}] Restoring session", client);
+
+            super::client::restore_session(state, &client, session);
+        }
+        Event::PasswordChecked {
+            client,
+            nick,
+            valid,
+        } => super::client::finish_claim(state, &client, &nick, valid, sender),
+        Event::PasswordHashed { client, nick, hash } => {
+            super::client::finish_register(state, &client, &nick, hash, sender)
         }
         Event::Dropped(client) => {
             log::debug!("[Client: {}] Dropped", client);
 
-            let mut clients = state.clients.lock();
-            let pos = clients.iter().position(|client_i| {
-                client_i == &client
-            }).unwrap();
+            let throttled = *client.throttled.read();
 
-            clients.remove(pos);
-        }
-    }
-}
+            if throttled > 0 {
+                log::info!("[Client: {}] Had {} messages throttled", client, throttled);
+            }
+
+            let dropped = client.outbound.lock().dropped;
 
-fn handle_incoming_client(state: &State, mut client_stream: TcpStream, client_tx: &Sender<Event>) {
-    let addr = client_stream.peer_addr().unwrap();
+            if dropped > 0 {
+                log::info!(
+                    "[Client: {}] Missed {} messages it was too slow for",
+                    client,
+                    dropped
+                );
+            }
+
+            let channels = state.part_all_channels(&client);
 
-    let _ = client_stream.set_nonblocking(true);
+            if let Some(token) = client.session.write().take() {
+                log::debug!("[Client: {}] Detaching session", client);
 
-    log::info!("Processing client: {}", addr.ip());
+                state.detach_session(&token, channels);
+            }
 
-    let client = match state.get_client_by_addr(&addr) {
-        Some(client) => {
-            let mut stream = client.stream.lock();
+            let mut clients = state.clients.lock();
+            let pos = clients
+                .iter()
+                .position(|client_i| client_i == &client)
+                .unwrap();
 
-            match stream.take_error() {
-                Ok(Some(ref e)) if e.kind() == ErrorKind::TimedOut => {
-                    log::info!("Client ({}) timed out and has rejoined.", addr.ip());
+            clients.remove(pos);
+        }
+    }
+}
 
-                    *stream = client_stream;
+pub fn handle_incoming_client(
+    state: &State,
+    registry: &Registry,
+    listener: &Listener,
+    token: Token,
+    mut stream: Stream,
+    peer: Peer,
+) {
+    if let Some(ban) = peer.ip().and_then(|ip| state.bans.find(&ip)) {
+        log::info!(
+            "[Listener: {}] Client ({}) was dropped: Banned by {}",
+            listener,
+            peer,
+            ban.by
+        );
+
+        // a TLS client can't read anything before the handshake
+        if !listener.listen.is_tls() {
+            let banned = ErrorMessage::Banned {
+                reason: ban.reason,
+                until: ban.until.map(|until| until.timestamp_millis()),
+            };
+
+            if let Ok(frame) = Message::from(banned).to_frame() {
+                let _ = stream.write_all(&frame);
+            }
+        }
 
-                    drop(stream);
+        return;
+    }
 
-                    client
-                }
+    log::info!("[Listener: {}] New client: {}", listener, peer);
 
-                _ => {
-                    let _ = Message::send(&mut client_stream, ErrorMessage::AlreadyConnected);
+    let stream = match (stream, &state.tls) {
+        (Stream::Plain(sock), Some(config)) if listener.listen.is_tls() => {
+            match TlsStream::new(sock, config.clone()) {
+                Ok(tls) => Stream::Tls(Box::new(tls)),
 
-                    log::info!("Client ({}) was dropped: Already joined.", addr.ip());
+                Err(e) => {
+                    log::warn!(
+                        "[Listener: {}] Client ({}) could not start TLS: {}",
+                        listener,
+                        peer,
+                        e
+                    );
                     return;
                 }
             }
         }
 
-        None => {
-            log::info!("New client: {}", addr.ip());
-
-            let client = Client::new(client_stream);
+        (stream, _) => stream,
+    };
 
-            state.clients.lock().push(client.clone());
+    let client = Client::new(stream, peer, token, &state.config());
 
-            client
-        }
-    };
+    if let Err(e) = registry.register(
+        &mut *client.stream.lock(),
+        token,
+        Interest::READABLE | Interest::WRITABLE,
+    ) {
+        log::warn!("Client ({}) could not be registered: {}", peer, e);
+        return;
+    }
 
-    let state = state.clone();
-    let client_tx = client_tx.clone();
+    log::info!("[Client: {}] Connected.", client);
 
-    let _ = spawn(move || client::handle(state, client, client_tx));
+    state.clients.lock().push(client);
 }
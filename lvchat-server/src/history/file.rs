@@ -0,0 +1,458 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+
+use crate::history::{Before, Entry, HistoryStore};
+
+/// Entries kept in memory per channel, enough for a full page of history.
+const TAIL_LEN: usize = 200;
+
+/// Bytes of pruned entries at the start of the log worth rewriting it for, unless they make up
+/// most of it anyway.
+const COMPACT_AFTER: u64 = 1 << 20;
+
+/// Append-only log with one JSON encoded entry per line.
+///
+/// Every entry is indexed by its position in the log, so older pages are read line by line
+/// instead of scanning the log, and only the newest entries of each channel are kept in memory
+/// as a whole. Entries expire in the order they were appended, so pruning just forgets them and
+/// compacting cuts them off the start of the log.
+#[derive(Debug)]
+pub struct FileHistory {
+    path: PathBuf,
+    log: Mutex<Log>,
+}
+
+#[derive(Debug)]
+struct Log {
+    file: File,
+
+    /// Offsets count from the first byte ever written to the log, `start` being the first one
+    /// still in the file.
+    start: u64,
+
+    /// Offset of the first entry that wasn't pruned.
+    live: u64,
+    end: u64,
+
+    last_seq: u64,
+    last_ts: Option<DateTime<Utc>>,
+    channels: HashMap<String, Channel>,
+}
+
+#[derive(Debug, Default)]
+struct Channel {
+    /// Every entry of the channel, oldest first.
+    index: VecDeque<Position>,
+
+    /// Newest entries, the same as the end of `index`.
+    tail: VecDeque<Entry>,
+}
+
+/// Where an entry is found in the log.
+#[derive(Debug, Clone, Copy)]
+struct Position {
+    seq: u64,
+    ts: DateTime<Utc>,
+    offset: u64,
+    len: usize,
+}
+
+impl Log {
+    /// Assigns `seq` unless the entry was written with one and raises `ts` to that of the
+    /// previous entry, then indexes it.
+    fn push(&mut self, mut entry: Entry, offset: u64, len: usize) -> Entry {
+        if entry.seq <= self.last_seq {
+            entry.seq = self.last_seq + 1;
+        }
+
+        if let Some(last_ts) = self.last_ts {
+            entry.ts = entry.ts.max(last_ts);
+        }
+
+        self.last_seq = entry.seq;
+        self.last_ts = Some(entry.ts);
+
+        let channel = self.channels.entry(entry.channel.clone()).or_default();
+
+        channel.index.push_back(Position {
+            seq: entry.seq,
+            ts: entry.ts,
+            offset,
+            len,
+        });
+        channel.tail.push_back(entry.clone());
+
+        if channel.tail.len() > TAIL_LEN {
+            channel.tail.pop_front();
+        }
+
+        entry
+    }
+
+    fn read(&mut self, position: &Position) -> io::Result<Entry> {
+        let mut line = vec![0; position.len];
+
+        self.file
+            .seek(SeekFrom::Start(position.offset - self.start))?;
+        self.file.read_exact(&mut line)?;
+
+        let mut entry: Entry = serde_json::from_slice(&line)?;
+
+        // both may have been assigned when the log was read
+        entry.seq = position.seq;
+        entry.ts = position.ts;
+
+        Ok(entry)
+    }
+}
+
+impl FileHistory {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)?;
+
+        let mut log = Log {
+            file,
+            start: 0,
+            live: 0,
+            end: 0,
+            last_seq: 0,
+            last_ts: None,
+            channels: HashMap::new(),
+        };
+
+        let end = read_log(&path, |entry, offset, len| {
+            log.push(entry, offset, len);
+        })?;
+
+        log.end = end;
+
+        Ok(FileHistory {
+            path,
+            log: Mutex::new(log),
+        })
+    }
+}
+
+impl HistoryStore for FileHistory {
+    fn append(&self, mut entry: Entry) -> io::Result<Entry> {
+        let mut log = self.log.lock();
+
+        // written with the seq it gets, so it survives reopening
+        entry.seq = log.last_seq + 1;
+
+        if let Some(last_ts) = log.last_ts {
+            entry.ts = entry.ts.max(last_ts);
+        }
+
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+
+        log.file.write_all(&line)?;
+
+        let offset = log.end;
+        log.end += line.len() as u64;
+
+        Ok(log.push(entry, offset, line.len() - 1))
+    }
+
+    fn fetch(&self, channel: &str, before: Option<Before>, limit: usize) -> io::Result<Vec<Entry>> {
+        let mut log = self.log.lock();
+
+        let positions = match log.channels.get(channel) {
+            Some(channel) => {
+                let end = match before {
+                    Some(Before::Seq(seq)) => channel.index.partition_point(|p| p.seq < seq),
+                    Some(Before::Time(ts)) => channel.index.partition_point(|p| p.ts < ts),
+                    None => channel.index.len(),
+                };
+                let start = end.saturating_sub(limit);
+                let tail_start = channel.index.len() - channel.tail.len();
+
+                // the newest entries are in memory as a whole
+                if start >= tail_start {
+                    return Ok(channel
+                        .tail
+                        .range(start - tail_start..end - tail_start)
+                        .cloned()
+                        .collect());
+                }
+
+                channel.index.range(start..end).copied().collect::<Vec<_>>()
+            }
+
+            None => return Ok(vec![]),
+        };
+
+        positions
+            .iter()
+            .map(|position| log.read(position))
+            .collect()
+    }
+
+    fn prune(&self, cutoff: DateTime<Utc>) -> io::Result<()> {
+        let mut log = self.log.lock();
+
+        for channel in log.channels.values_mut() {
+            let expired = channel.index.partition_point(|p| p.ts < cutoff);
+
+            channel.index.drain(..expired);
+
+            while channel.tail.len() > channel.index.len() {
+                channel.tail.pop_front();
+            }
+        }
+
+        log.channels.retain(|_, channel| !channel.index.is_empty());
+
+        log.live = log
+            .channels
+            .values()
+            .filter_map(|channel| channel.index.front())
+            .map(|position| position.offset)
+            .min()
+            .unwrap_or(log.end);
+
+        Ok(())
+    }
+
+    /// Rewrites the log without the pruned entries at its start, if they take up enough space.
+    ///
+    /// The bulk is copied without blocking appends, only what was appended meanwhile is copied
+    /// while holding the lock.
+    fn compact(&self) -> io::Result<()> {
+        let (start, live, end) = {
+            let log = self.log.lock();
+
+            (log.start, log.live, log.end)
+        };
+
+        let pruned = live - start;
+
+        if pruned == 0 || (pruned < COMPACT_AFTER && pruned < end - live) {
+            return Ok(());
+        }
+
+        // rewrite into a temporary file first, so a crash never leaves a truncated log behind
+        let compacted = self.path.with_extension("compact");
+
+        let mut reader = File::open(&self.path)?;
+        let mut writer = BufWriter::new(File::create(&compacted)?);
+
+        reader.seek(SeekFrom::Start(pruned))?;
+        io::copy(&mut (&mut reader).take(end - live), &mut writer)?;
+
+        let mut log = self.log.lock();
+
+        io::copy(&mut reader.take(log.end - end), &mut writer)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+
+        fs::rename(&compacted, &self.path)?;
+
+        log.file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&self.path)?;
+        log.start = live;
+
+        log::info!("Compacted history, dropping {} bytes", pruned);
+
+        Ok(())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.log.lock().file.sync_all()
+    }
+}
+
+/// Passes every entry of the log at `path` to `f`, oldest first, along with the offset and
+/// length of its line, and returns the length of the log.
+///
+/// Corrupt lines are skipped.
+fn read_log<F>(path: &Path, mut f: F) -> io::Result<u64>
+where
+    F: FnMut(Entry, u64, usize),
+{
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut line = vec![];
+    let mut offset = 0;
+
+    for n in 1.. {
+        line.clear();
+
+        let read = reader.read_until(b'\n', &mut line)?;
+
+        if read == 0 {
+            break;
+        }
+
+        let content = line.strip_suffix(b"\n").unwrap_or(&line);
+
+        if !content.iter().all(u8::is_ascii_whitespace) {
+            match serde_json::from_slice(content) {
+                Ok(entry) => f(entry, offset, content.len()),
+
+                Err(e) => {
+                    log::warn!(
+                        "Skipping corrupt history entry {}:{}: {}",
+                        path.display(),
+                        n,
+                        e
+                    );
+                }
+            }
+        }
+
+        offset += read as u64;
+    }
+
+    Ok(offset)
+}
+
+#[test]
+fn reopen_and_prune() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("history.log");
+
+    {
+        let history = FileHistory::open(&path).unwrap();
+
+        for n in 0..10 {
+            let mut old = Entry::new("#lobby", "alice", format!("old {}", n));
+            old.ts = old.ts - chrono::Duration::days(2);
+
+            history.append(old).unwrap();
+        }
+
+        history.append(Entry::new("#lobby", "bob", "new")).unwrap();
+        history
+            .append(Entry::new("#other", "bob", "elsewhere"))
+            .unwrap();
+    }
+
+    let history = FileHistory::open(&path).unwrap();
+    let lobby = history.fetch("#lobby", None, 20).unwrap();
+
+    assert_eq!(lobby.len(), 11);
+    assert_eq!(lobby[0].message, "old 0");
+    assert_eq!(history.fetch("#lobby", None, 1).unwrap()[0].message, "new");
+
+    history
+        .prune(Utc::now() - chrono::Duration::days(1))
+        .unwrap();
+
+    assert_eq!(history.fetch("#lobby", None, 20).unwrap().len(), 1);
+
+    history.compact().unwrap();
+    history
+        .append(Entry::new("#lobby", "carol", "after"))
+        .unwrap();
+
+    assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 3);
+    assert_eq!(
+        history.fetch("#lobby", Some(Before::Seq(13)), 20).unwrap()[0].message,
+        "new"
+    );
+    drop(history);
+
+    let history = FileHistory::open(&path).unwrap();
+    let lobby = history.fetch("#lobby", None, 20).unwrap();
+
+    assert_eq!(
+        lobby
+            .iter()
+            .map(|e| (e.seq, e.message.as_str()))
+            .collect::<Vec<_>>(),
+        [(11, "new"), (13, "after")]
+    );
+    assert_eq!(history.fetch("#other", None, 10).unwrap().len(), 1);
+}
+
+#[test]
+fn read_older_pages_from_log() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("history.log");
+    let start = Utc::now() - chrono::Duration::hours(1);
+
+    {
+        let history = FileHistory::open(&path).unwrap();
+
+        for n in 0..TAIL_LEN as i64 * 2 {
+            let mut entry = Entry::new("#lobby", "alice", n.to_string());
+            entry.ts = start + chrono::Duration::milliseconds(n);
+
+            history.append(entry).unwrap();
+        }
+
+        history.append(Entry::new("#other", "bob", "hi")).unwrap();
+    }
+
+    let history = FileHistory::open(&path).unwrap();
+
+    assert_eq!(history.log.lock().channels["#lobby"].tail.len(), TAIL_LEN);
+
+    let newest = history.fetch("#lobby", None, 10).unwrap();
+
+    assert_eq!(newest.len(), 10);
+    assert_eq!(newest[9].message, (TAIL_LEN * 2 - 1).to_string());
+
+    let oldest = history
+        .fetch("#lobby", Some(Before::Time(newest[0].ts)), TAIL_LEN * 2)
+        .unwrap();
+
+    assert_eq!(oldest.len(), TAIL_LEN * 2 - 10);
+    assert_eq!(oldest[0].message, "0");
+    assert_eq!(
+        oldest.last().unwrap().ts,
+        newest[0].ts - chrono::Duration::milliseconds(1)
+    );
+
+    assert_eq!(history.fetch("#other", None, 10).unwrap().len(), 1);
+    assert!(history.fetch("#nobody", None, 10).unwrap().is_empty());
+}
+
+#[test]
+fn page_through_entries_sharing_a_timestamp() {
+    let dir = tempfile::tempdir().unwrap();
+    let history = FileHistory::open(dir.path().join("history.log")).unwrap();
+    let ts = Utc::now();
+
+    for n in 0..TAIL_LEN * 3 {
+        let mut entry = Entry::new("#lobby", "alice", n.to_string());
+        entry.ts = ts;
+
+        history.append(entry).unwrap();
+    }
+
+    let mut before = None;
+    let mut seen = vec![];
+
+    loop {
+        let page = history.fetch("#lobby", before, 70).unwrap();
+
+        match page.first() {
+            Some(first) => before = Some(Before::Seq(first.seq)),
+            None => break,
+        }
+
+        seen.splice(0..0, page.into_iter().map(|entry| entry.message));
+    }
+
+    assert_eq!(
+        seen,
+        (0..TAIL_LEN * 3).map(|n| n.to_string()).collect::<Vec<_>>()
+    );
+}
@@ -0,0 +1,56 @@
+use std::{collections::VecDeque, io};
+
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+
+use crate::history::{Before, Entry, HistoryStore};
+
+/// Keeps history for the lifetime of the server only.
+#[derive(Debug, Default)]
+pub struct MemoryHistory {
+    entries: Mutex<VecDeque<Entry>>,
+
+    /// `seq` of the last entry ever appended, which may have been pruned since.
+    last_seq: Mutex<u64>,
+}
+
+impl HistoryStore for MemoryHistory {
+    fn append(&self, mut entry: Entry) -> io::Result<Entry> {
+        let mut entries = self.entries.lock();
+        let mut last_seq = self.last_seq.lock();
+
+        *last_seq += 1;
+        entry.seq = *last_seq;
+
+        if let Some(last) = entries.back() {
+            entry.ts = entry.ts.max(last.ts);
+        }
+
+        entries.push_back(entry.clone());
+
+        Ok(entry)
+    }
+
+    fn fetch(&self, channel: &str, before: Option<Before>, limit: usize) -> io::Result<Vec<Entry>> {
+        let entries = self.entries.lock();
+
+        let mut found = entries
+            .iter()
+            .rev()
+            .filter(|entry| entry.channel == channel)
+            .filter(|entry| before.is_none_or(|before| before.includes(entry)))
+            .take(limit)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        found.reverse();
+
+        Ok(found)
+    }
+
+    fn prune(&self, cutoff: DateTime<Utc>) -> io::Result<()> {
+        self.entries.lock().retain(|entry| entry.ts >= cutoff);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+//! Storage of relayed channel messages.
+//!
+//! Private messages are never recorded.
+
+use std::{fmt::Debug, io, sync::Arc};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use lvchat_core::{HistoryEntry, RecordedMessage};
+
+use crate::config::Config;
+
+pub use self::{file::FileHistory, memory::MemoryHistory};
+
+pub mod file;
+pub mod memory;
+
+/// Recorded channel message.
+///
+/// Entries are ordered by `(ts, seq)`. As stores never let `ts` go backwards, `seq` alone is a
+/// strict cursor, even for entries sharing a timestamp.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Assigned by the store, starting at 1. Missing from logs written by older servers.
+    #[serde(default)]
+    pub seq: u64,
+    pub ts: DateTime<Utc>,
+    pub channel: String,
+    pub sender: String,
+    pub message: String,
+}
+
+impl Entry {
+    pub fn new<C, S, M>(channel: C, sender: S, message: M) -> Self
+    where
+        C: Into<String>,
+        S: Into<String>,
+        M: Into<String>,
+    {
+        Entry {
+            seq: 0,
+            ts: Utc::now(),
+            channel: channel.into(),
+            sender: sender.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl From<Entry> for RecordedMessage {
+    fn from(entry: Entry) -> Self {
+        RecordedMessage {
+            id: entry.seq,
+            timestamp: entry.ts.timestamp_millis(),
+            user: entry.sender,
+            message: entry.message,
+        }
+    }
+}
+
+impl From<Entry> for HistoryEntry {
+    fn from(entry: Entry) -> Self {
+        RecordedMessage::from(entry).into()
+    }
+}
+
+/// Exclusive end of a page of history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Before {
+    /// Entries recorded before the one with this `seq`.
+    Seq(u64),
+
+    /// Entries recorded before this time, for clients of revisions without message ids.
+    Time(DateTime<Utc>),
+}
+
+impl Before {
+    pub fn includes(&self, entry: &Entry) -> bool {
+        match *self {
+            Before::Seq(seq) => entry.seq < seq,
+            Before::Time(ts) => entry.ts < ts,
+        }
+    }
+}
+
+pub trait HistoryStore: Debug + Send + Sync {
+    /// Records the entry and returns it with its `seq`, and its `ts` raised to that of the
+    /// previous entry if the clock went backwards.
+    fn append(&self, entry: Entry) -> io::Result<Entry>;
+
+    /// Returns up to `limit` of the newest entries of `channel` before `before`, oldest first.
+    fn fetch(&self, channel: &str, before: Option<Before>, limit: usize) -> io::Result<Vec<Entry>>;
+
+    /// Stops returning entries older than `cutoff`.
+    ///
+    /// Cheap enough for the reactor thread, unlike `compact`.
+    fn prune(&self, cutoff: DateTime<Utc>) -> io::Result<()>;
+
+    /// Frees the space of pruned entries, if worth it.
+    fn compact(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Makes sure every appended entry reached persistent storage.
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Opens the store configured by `--history`, falling back to memory if no path is given.
+pub fn open(config: &Config) -> io::Result<Arc<dyn HistoryStore>> {
+    let store: Arc<dyn HistoryStore> = match config.history_path {
+        Some(ref path) => Arc::new(FileHistory::open(path)?),
+        None => Arc::new(MemoryHistory::default()),
+    };
+
+    if let Some(cutoff) = retention_cutoff(config) {
+        store.prune(cutoff)?;
+        store.compact()?;
+    }
+
+    Ok(store)
+}
+
+/// Point in time before which entries expire, or `None` if they are kept forever.
+pub fn retention_cutoff(config: &Config) -> Option<DateTime<Utc>> {
+    match config.history_retention_days {
+        0 => None,
+        days => Some(Utc::now() - Duration::days(i64::from(days))),
+    }
+}
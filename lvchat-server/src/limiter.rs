@@ -0,0 +1,167 @@
+//! Flood protection through a token bucket per client.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Throttled messages dropped silently before the client is warned.
+pub const WARN_AFTER: u32 = 3;
+
+/// Time after which a client is forgiven one throttled message.
+pub const VIOLATION_DECAY: Duration = Duration::from_secs(10);
+
+/// Allows bursts of up to `capacity` messages, refilled at `rate` messages per second.
+///
+/// The limits are passed on every acquisition, so reloading the configuration applies them to
+/// connected clients as well.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    tokens: f64,
+    last: Instant,
+    forgiven: Instant,
+
+    /// Messages throttled recently, forgotten one per `VIOLATION_DECAY`.
+    pub violations: u32,
+}
+
+impl TokenBucket {
+    /// Returns a full bucket.
+    pub fn new(now: Instant) -> Self {
+        TokenBucket {
+            tokens: f64::INFINITY,
+            last: now,
+            forgiven: now,
+            violations: 0,
+        }
+    }
+
+    /// Takes a token, or returns how long until the next one is available.
+    pub fn acquire(&mut self, rate: f64, capacity: u32, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * rate).min(capacity as f64);
+        self.last = now;
+
+        self.forgive(now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+
+            Ok(())
+        } else {
+            self.violations += 1;
+
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+        }
+    }
+
+    fn forgive(&mut self, now: Instant) {
+        if self.violations == 0 {
+            self.forgiven = now;
+            return;
+        }
+
+        let periods = now.saturating_duration_since(self.forgiven).as_secs_f64()
+            / VIOLATION_DECAY.as_secs_f64();
+        let periods = periods.min(u32::MAX as f64) as u32;
+
+        if periods >= self.violations {
+            self.violations = 0;
+            self.forgiven = now;
+        } else {
+            self.violations -= periods;
+            self.forgiven += VIOLATION_DECAY * periods;
+        }
+    }
+}
+
+/// Counters of throttled traffic since the server started.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub throttled: AtomicU64,
+    pub warned: AtomicU64,
+    pub disconnected: AtomicU64,
+}
+
+impl Metrics {
+    pub fn count(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl std::fmt::Display for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} messages throttled, {} warnings, {} clients disconnected",
+            self.throttled.load(Ordering::Relaxed),
+            self.warned.load(Ordering::Relaxed),
+            self.disconnected.load(Ordering::Relaxed)
+        )
+    }
+}
+
+#[test]
+fn burst_then_refill() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(start);
+
+    for _ in 0..3 {
+        assert!(bucket.acquire(2.0, 3, start).is_ok());
+    }
+
+    assert_eq!(
+        bucket.acquire(2.0, 3, start),
+        Err(Duration::from_millis(500))
+    );
+    assert!(bucket.acquire(2.0, 3, start).is_err());
+    assert_eq!(bucket.violations, 2);
+
+    let later = start + Duration::from_millis(500);
+
+    assert!(bucket.acquire(2.0, 3, later).is_ok());
+    assert!(bucket.acquire(2.0, 3, later).is_err());
+
+    // never refills beyond its capacity
+    let later = start + Duration::from_secs(60);
+
+    for _ in 0..3 {
+        assert!(bucket.acquire(2.0, 3, later).is_ok());
+    }
+
+    assert!(bucket.acquire(2.0, 3, later).is_err());
+
+    // a reloaded configuration applies to the next acquisition
+    let later = later + Duration::from_secs(60);
+
+    for _ in 0..5 {
+        assert!(bucket.acquire(2.0, 5, later).is_ok());
+    }
+
+    assert!(bucket.acquire(2.0, 5, later).is_err());
+}
+
+#[test]
+fn violations_decay_over_time() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(start);
+
+    assert!(bucket.acquire(1.0, 1, start).is_ok());
+
+    // sending twice as fast as allowed lets every other message through, but keeps escalating
+    for i in 1..=20 {
+        let _ = bucket.acquire(1.0, 1, start + Duration::from_millis(i * 500));
+    }
+
+    assert!(bucket.violations >= 5);
+
+    let violations = bucket.violations;
+    let calm = start + Duration::from_secs(10) + VIOLATION_DECAY * 3;
+
+    assert!(bucket.acquire(1.0, 1, calm).is_ok());
+    assert_eq!(bucket.violations, violations - 3);
+
+    let _ = bucket.acquire(1.0, 1, calm + VIOLATION_DECAY * violations);
+    assert_eq!(bucket.violations, 0);
+}
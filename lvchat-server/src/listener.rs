@@ -0,0 +1,296 @@
+//! Addresses the server accepts clients on, given by `--listen`.
+
+use std::{convert::TryFrom, fmt, io, net::SocketAddr, str::FromStr};
+#[cfg(unix)]
+use std::{
+    fs,
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+};
+
+#[cfg(unix)]
+use mio::net::UnixListener;
+use mio::{event::Source, net::TcpListener, Interest, Registry, Token};
+use serde::Deserialize;
+use socket2::{Domain, Protocol, Type};
+
+use lvchat_core::Peer;
+
+use crate::stream::Stream;
+
+/// Address to listen on, written like `127.0.0.1:5050`, `[::]:5050`, `tls://[::]:5443` or
+/// `unix:/run/lvchat.sock`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Listen {
+    Tcp {
+        addr: SocketAddr,
+
+        /// Whether clients have to connect over TLS.
+        tls: bool,
+    },
+
+    /// Unix domain socket for clients on the same host.
+    #[cfg(unix)]
+    Unix { path: PathBuf },
+}
+
+impl Listen {
+    pub fn is_tls(&self) -> bool {
+        matches!(self, Listen::Tcp { tls: true, .. })
+    }
+
+    /// Whether both can't be bound at the same time.
+    pub fn conflicts(&self, other: &Listen) -> bool {
+        match (self, other) {
+            (Listen::Tcp { addr, .. }, Listen::Tcp { addr: other, .. }) => addr == other,
+            #[cfg(unix)]
+            (Listen::Unix { path }, Listen::Unix { path: other }) => path == other,
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Listen {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        #[cfg(unix)]
+        if let Some(path) = s.strip_prefix("unix:") {
+            return match path.trim_start_matches("//") {
+                "" => Err("Unix socket path must not be empty".to_owned()),
+                path => Ok(Listen::Unix { path: path.into() }),
+            };
+        }
+
+        #[cfg(not(unix))]
+        if s.starts_with("unix:") {
+            return Err("Unix domain sockets aren't supported on this platform".to_owned());
+        }
+
+        let (addr, tls) = match s.strip_prefix("tls://") {
+            Some(addr) => (addr, true),
+            None => (s.strip_prefix("tcp://").unwrap_or(s), false),
+        };
+
+        let addr = addr.parse().map_err(|_| {
+            format!(
+                "Invalid listen address '{}', expected something like 127.0.0.1:5050, [::]:5050, \
+                 tls://0.0.0.0:5443 or unix:/run/lvchat.sock",
+                s
+            )
+        })?;
+
+        Ok(Listen::Tcp { addr, tls })
+    }
+}
+
+impl TryFrom<String> for Listen {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Listen {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Listen::Tcp { addr, tls: true } => write!(f, "tls://{}", addr),
+            Listen::Tcp { addr, tls: false } => write!(f, "{}", addr),
+            #[cfg(unix)]
+            Listen::Unix { path } => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Socket {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+/// Bound socket accepting clients.
+#[derive(Debug)]
+pub struct Listener {
+    socket: Socket,
+    pub listen: Listen,
+}
+
+impl Listener {
+    pub fn bind(listen: Listen) -> io::Result<Self> {
+        let socket = match listen {
+            Listen::Tcp { addr, .. } => Socket::Tcp(bind_tcp(addr)?),
+
+            #[cfg(unix)]
+            Listen::Unix { ref path } => {
+                if fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+                    remove_stale_socket(path)?;
+                }
+
+                Socket::Unix(UnixListener::bind(path)?)
+            }
+        };
+
+        Ok(Listener { socket, listen })
+    }
+
+    /// Accepts a pending client, not yet wrapped into TLS.
+    pub fn accept(&self) -> io::Result<(Stream, Peer)> {
+        match self.socket {
+            Socket::Tcp(ref listener) => {
+                let (stream, addr) = listener.accept()?;
+
+                Ok((Stream::Plain(stream), Peer::Tcp(addr)))
+            }
+
+            #[cfg(unix)]
+            Socket::Unix(ref listener) => {
+                let (stream, _) = listener.accept()?;
+
+                Ok((Stream::Unix(stream), Peer::Local))
+            }
+        }
+    }
+
+    /// Bound TCP address, with the actual port if port 0 was configured.
+    pub fn local_addr(&self) -> io::Result<Option<SocketAddr>> {
+        match self.socket {
+            Socket::Tcp(ref listener) => listener.local_addr().map(Some),
+            #[cfg(unix)]
+            Socket::Unix(_) => Ok(None),
+        }
+    }
+
+    fn source(&mut self) -> &mut dyn Source {
+        match self.socket {
+            Socket::Tcp(ref mut listener) => listener,
+            #[cfg(unix)]
+            Socket::Unix(ref mut listener) => listener,
+        }
+    }
+}
+
+/// Removes a socket left behind by a server that didn't shut down cleanly, failing with
+/// `AddrInUse` if one still accepts clients on it.
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    match std::os::unix::net::UnixStream::connect(path) {
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => fs::remove_file(path),
+
+        _ => Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("{} is in use by another server", path.display()),
+        )),
+    }
+}
+
+/// Binds `addr`. IPv6 sockets only take IPv6 clients, so the same port can be bound for IPv4
+/// separately.
+fn bind_tcp(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket =
+        socket2::Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+
+    Ok(TcpListener::from_std(socket.into()))
+}
+
+impl Source for Listener {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        self.source().register(registry, token, interests)
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        self.source().reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        self.source().deregister(registry)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listen::Unix { ref path } = self.listen {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.listen)
+    }
+}
+
+#[test]
+fn parse_listen_addresses() {
+    let listen = "tls://[::1]:5443".parse::<Listen>().unwrap();
+
+    assert!(listen.is_tls());
+    assert_eq!(listen.to_string(), "tls://[::1]:5443");
+
+    assert_eq!(
+        "127.0.0.1:5050".parse::<Listen>(),
+        "tcp://127.0.0.1:5050".parse::<Listen>()
+    );
+    assert!(!"0.0.0.0:5050".parse::<Listen>().unwrap().is_tls());
+    assert!("localhost:5050".parse::<Listen>().is_err());
+    assert!("udp://0.0.0.0:5050".parse::<Listen>().is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn parse_unix_socket_paths() {
+    assert_eq!(
+        "unix:///run/lvchat.sock".parse::<Listen>(),
+        Ok(Listen::Unix {
+            path: "/run/lvchat.sock".into()
+        })
+    );
+    assert_eq!(
+        "unix:lvchat.sock".parse::<Listen>().unwrap().to_string(),
+        "unix:lvchat.sock"
+    );
+    assert!("unix:".parse::<Listen>().is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn keep_sockets_of_running_servers() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("lvchat.sock");
+    let listen = Listen::Unix { path: path.clone() };
+
+    // a socket nobody listens on anymore is replaced
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+    let running = Listener::bind(listen.clone()).unwrap();
+
+    let e = Listener::bind(listen).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::AddrInUse);
+    assert!(path.exists());
+
+    drop(running);
+    assert!(!path.exists());
+}
@@ -29,7 +29,14 @@ fn init_logger(config: &Config) {
 }
 
 fn main() -> Result<(), Error> {
-    let config = Config::init();
+    let config = match Config::init() {
+        Ok(config) => config,
+
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
 
     init_logger(&config);
 
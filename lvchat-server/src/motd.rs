@@ -0,0 +1,57 @@
+//! Message of the day, read from the file given by `--motd`.
+//!
+//! The file may contain the placeholders `{server}`, `{users}` and `{uptime}`, which are filled
+//! in for every client receiving it.
+
+use std::{fs, io, time::Duration};
+
+use crate::config::Config;
+
+/// Reads the configured template, `None` if no `--motd` was given.
+pub fn load(config: &Config) -> io::Result<Option<String>> {
+    match config.motd_path {
+        Some(ref path) => Ok(Some(fs::read_to_string(path)?.trim_end().to_owned())),
+        None => Ok(None),
+    }
+}
+
+pub fn render(template: &str, server: &str, users: usize, uptime: Duration) -> String {
+    template
+        .replace("{server}", server)
+        .replace("{users}", &users.to_string())
+        .replace("{uptime}", &format_uptime(uptime))
+}
+
+/// Formats `uptime` like `2d 3h 4m`, leaving out leading zero units.
+fn format_uptime(uptime: Duration) -> String {
+    let secs = uptime.as_secs();
+
+    let (days, hours, minutes) = (secs / 86_400, secs / 3_600 % 24, secs / 60 % 60);
+
+    if days > 0 {
+        format!("{}d {}h {}m", days, hours, minutes)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else {
+        format!("{}m", minutes)
+    }
+}
+
+#[test]
+fn render_placeholders() {
+    let template = "Welcome to {server}! {users} online, up for {uptime}. {unknown}";
+
+    assert_eq!(
+        render(
+            template,
+            "lvchat",
+            3,
+            Duration::from_secs(2 * 86_400 + 3 * 3_600 + 4 * 60)
+        ),
+        "Welcome to lvchat! 3 online, up for 2d 3h 4m. {unknown}"
+    );
+    assert_eq!(
+        render("{uptime}", "lvchat", 0, Duration::from_secs(3_599)),
+        "59m"
+    );
+}
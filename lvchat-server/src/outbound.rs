@@ -0,0 +1,196 @@
+//! Frames waiting to be written to a client whose socket doesn't keep up.
+
+use std::{
+    collections::VecDeque,
+    io::{self, ErrorKind, Write},
+    str::FromStr,
+};
+
+use serde::Deserialize;
+
+/// What happens when a client's outbound queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OverflowPolicy {
+    /// Drop the oldest queued frames to make room.
+    DropOldest,
+
+    /// Disconnect the client.
+    Disconnect,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop-oldest" => Ok(OverflowPolicy::DropOldest),
+            "disconnect" => Ok(OverflowPolicy::Disconnect),
+            other => Err(format!(
+                "Unknown overflow policy '{}', expected 'drop-oldest' or 'disconnect'",
+                other
+            )),
+        }
+    }
+}
+
+/// Bounded queue of encoded frames.
+///
+/// Frames are only ever written or dropped whole, so a client never receives a truncated one.
+#[derive(Debug)]
+pub struct OutboundQueue {
+    frames: VecDeque<Vec<u8>>,
+
+    /// Bytes of the first frame already written.
+    written: usize,
+
+    capacity: usize,
+    policy: OverflowPolicy,
+
+    /// Frames dropped to make room since the client connected.
+    pub dropped: u64,
+}
+
+impl OutboundQueue {
+    pub fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        OutboundQueue {
+            frames: VecDeque::new(),
+            written: 0,
+            capacity: capacity.max(1),
+            policy,
+            dropped: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Queues `frame`, failing if the queue is full and the policy is to disconnect.
+    pub fn push(&mut self, frame: Vec<u8>) -> io::Result<()> {
+        while self.frames.len() >= self.capacity {
+            match self.policy {
+                OverflowPolicy::Disconnect => {
+                    return Err(io::Error::other("Outbound queue is full"));
+                }
+
+                // a partially written frame has to be finished, so with nothing else queued
+                // the new frame is the oldest one that can go
+                OverflowPolicy::DropOldest if self.written > 0 => match self.frames.remove(1) {
+                    Some(_) => self.dropped += 1,
+                    None => {
+                        self.dropped += 1;
+                        return Ok(());
+                    }
+                },
+
+                OverflowPolicy::DropOldest => {
+                    self.frames.pop_front();
+                    self.dropped += 1;
+                }
+            }
+        }
+
+        self.frames.push_back(frame);
+
+        Ok(())
+    }
+
+    /// Writes queued frames until `writer` would block.
+    pub fn write_to<W: Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        while let Some(frame) = self.frames.front() {
+            match writer.write(&frame[self.written..]) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+
+                Ok(size) => {
+                    self.written += size;
+
+                    if self.written == frame.len() {
+                        self.frames.pop_front();
+                        self.written = 0;
+                    }
+                }
+
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn overflow_policies() {
+    /// Accepts a few bytes per write, then blocks.
+    struct Trickle(Vec<u8>, usize);
+
+    impl Write for Trickle {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.1 == 0 {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+
+            let size = buf.len().min(self.1).min(2);
+
+            self.0.extend_from_slice(&buf[..size]);
+            self.1 -= size;
+
+            Ok(size)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut queue = OutboundQueue::new(2, OverflowPolicy::DropOldest);
+    let mut sink = Trickle(vec![], 2);
+
+    queue.push(b"abc".to_vec()).unwrap();
+    queue.write_to(&mut sink).unwrap();
+    queue.push(b"def".to_vec()).unwrap();
+    queue.push(b"ghi".to_vec()).unwrap();
+
+    // "abc" is partially written, so "def" made room
+    assert_eq!(queue.len(), 2);
+    assert_eq!(queue.dropped, 1);
+
+    sink.1 = usize::MAX;
+    queue.write_to(&mut sink).unwrap();
+
+    assert!(queue.is_empty());
+    assert_eq!(sink.0, b"abcghi");
+
+    // nothing but the partially written frame to make room with
+    let mut queue = OutboundQueue::new(1, OverflowPolicy::DropOldest);
+    let mut sink = Trickle(vec![], 2);
+
+    queue.push(b"abc".to_vec()).unwrap();
+    queue.write_to(&mut sink).unwrap();
+    queue.push(b"def".to_vec()).unwrap();
+    queue.push(b"ghi".to_vec()).unwrap();
+
+    assert_eq!(queue.len(), 1);
+    assert_eq!(queue.dropped, 2);
+
+    sink.1 = usize::MAX;
+    queue.write_to(&mut sink).unwrap();
+
+    assert_eq!(sink.0, b"abc");
+
+    let mut queue = OutboundQueue::new(1, OverflowPolicy::Disconnect);
+
+    queue.push(b"abc".to_vec()).unwrap();
+    assert!(queue.push(b"def".to_vec()).is_err());
+
+    assert_eq!("drop-oldest".parse(), Ok(OverflowPolicy::DropOldest));
+    assert!("sometimes".parse::<OverflowPolicy>().is_err());
+}
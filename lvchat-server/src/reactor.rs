@@ -0,0 +1,387 @@
+use std::{
+    io::{self, ErrorKind},
+    net::{Shutdown, SocketAddr},
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use flume::Receiver;
+use mio::{event::Event as ReadinessEvent, Events, Interest, Poll, Token, Waker};
+use signal_hook::{
+    consts::{SIGHUP, SIGINT, SIGTERM},
+    low_level::signal_name,
+};
+use signal_hook_mio::v1_0::Signals;
+
+use lvchat_core::{ServerMessage, UserMessage};
+
+use crate::{
+    event::{Event, EventSender},
+    handler, history,
+    listener::Listener,
+    state::State,
+};
+
+/// Listeners follow with the tokens right after, clients after those.
+const SIGNALS: Token = Token(0);
+
+/// Wakes the reactor once the worker queued an event.
+const WAKER: Token = Token(usize::MAX);
+
+/// Reason given to clients when the server goes down.
+const SHUTDOWN_MESSAGE: &str = "Server is shutting down";
+
+/// Interval of periodic maintenance like expiring history.
+const HOUSEKEEPING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Single threaded event loop serving every client connection.
+///
+/// Sockets are only touched once the OS reports them ready, so idle connections cost nothing.
+pub struct Reactor {
+    state: State,
+    poll: Poll,
+    listeners: Vec<Listener>,
+    signals: Signals,
+    event_tx: EventSender,
+    event_rx: Receiver<Event>,
+    next_token: usize,
+    last_housekeeping: Instant,
+    last_keepalive: Instant,
+
+    /// Throttled messages at the time the metrics were last logged.
+    reported_throttled: u64,
+}
+
+impl Reactor {
+    pub fn bind(state: State) -> io::Result<Self> {
+        let poll = Poll::new()?;
+        let mut listeners = vec![];
+
+        for (i, listen) in state.config().listen.iter().enumerate() {
+            let mut listener = Listener::bind(listen.clone()).map_err(|e| {
+                io::Error::new(e.kind(), format!("Failed to listen on {}: {}", listen, e))
+            })?;
+
+            poll.registry().register(
+                &mut listener,
+                Token(SIGNALS.0 + 1 + i),
+                Interest::READABLE,
+            )?;
+
+            listeners.push(listener);
+        }
+
+        let mut signals = Signals::new([SIGHUP, SIGINT, SIGTERM])?;
+
+        poll.registry()
+            .register(&mut signals, SIGNALS, Interest::READABLE)?;
+
+        let (event_tx, event_rx) = flume::unbounded();
+        let waker = Waker::new(poll.registry(), WAKER)?;
+
+        Ok(Reactor {
+            state,
+            poll,
+            next_token: SIGNALS.0 + 1 + listeners.len(),
+            listeners,
+            signals,
+            event_tx: EventSender::new(event_tx, Arc::new(waker)),
+            event_rx,
+            last_housekeeping: Instant::now(),
+            last_keepalive: Instant::now(),
+            reported_throttled: 0,
+        })
+    }
+
+    /// Bound TCP addresses, in the order the listeners were configured.
+    pub fn local_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        self.listeners
+            .iter()
+            .filter_map(|listener| listener.local_addr().transpose())
+            .collect()
+    }
+
+    /// Serves clients until SIGINT or SIGTERM is received, then shuts down gracefully.
+    pub fn run(mut self) -> io::Result<()> {
+        let mut events = Events::with_capacity(1024);
+
+        for listener in &self.listeners {
+            match listener.local_addr()? {
+                Some(addr) => log::info!("[Listener: {}] Listening on {}", listener, addr),
+                None => log::info!("[Listener: {}] Listening", listener),
+            }
+        }
+
+        let mut shutdown = false;
+
+        while !shutdown {
+            if let Err(e) = self.poll.poll(&mut events, Some(self.timeout())) {
+                if e.kind() == ErrorKind::Interrupted {
+                    continue;
+                }
+
+                return Err(e);
+            }
+
+            for event in events.iter() {
+                match event.token() {
+                    SIGNALS => shutdown |= self.handle_signals(),
+                    WAKER => (),
+                    Token(i) if i <= self.listeners.len() => self.accept(i - 1),
+                    _ => self.handle_readiness(event),
+                }
+            }
+
+            if let Some(interval) = self.state.config().keepalive_interval() {
+                if self.last_keepalive.elapsed() >= interval {
+                    self.keepalive(interval);
+                }
+            }
+
+            self.process_events();
+
+            if self.last_housekeeping.elapsed() >= HOUSEKEEPING_INTERVAL {
+                self.housekeeping();
+            }
+        }
+
+        self.shutdown()
+    }
+
+    /// Reloads on SIGHUP and returns whether a shutdown was requested.
+    fn handle_signals(&mut self) -> bool {
+        let mut shutdown = false;
+
+        for signal in self.signals.pending() {
+            log::info!(
+                "Received {}",
+                signal_name(signal).unwrap_or("unknown signal")
+            );
+
+            match signal {
+                SIGHUP => {
+                    self.state.reload_config();
+                    self.state.reload_motd();
+                }
+                _ => shutdown = true,
+            }
+        }
+
+        shutdown
+    }
+
+    /// Stops accepting, tells every client and waits until their queues drained or the
+    /// shutdown timeout passed.
+    fn shutdown(mut self) -> io::Result<()> {
+        for listener in &mut self.listeners {
+            let _ = self.poll.registry().deregister(listener);
+        }
+
+        let clients = self.state.clients.lock().clone();
+
+        for client in &clients {
+            let _ = client.send(ServerMessage::Shutdown {
+                message: Some(SHUTDOWN_MESSAGE.to_owned()),
+            });
+        }
+
+        if let Err(e) = self.state.history.flush() {
+            log::warn!("Failed to flush history: {}", e);
+        }
+
+        let deadline = Instant::now() + self.state.config().shutdown_timeout();
+        let mut events = Events::with_capacity(1024);
+
+        loop {
+            let pending = clients
+                .iter()
+                .filter(|client| *client.active.read() && !client.outbound.lock().is_empty())
+                .count();
+
+            let now = Instant::now();
+
+            if pending == 0 {
+                break;
+            }
+
+            if now >= deadline {
+                log::warn!("Gave up on {} clients with undelivered messages", pending);
+                break;
+            }
+
+            if let Err(e) = self.poll.poll(&mut events, Some(deadline - now)) {
+                if e.kind() == ErrorKind::Interrupted {
+                    continue;
+                }
+
+                return Err(e);
+            }
+
+            for event in events.iter().filter(|event| event.is_writable()) {
+                if let Some(client) = self.state.get_client_by_token(event.token()) {
+                    handler::client::handle_writable(&client);
+                }
+            }
+        }
+
+        for client in &clients {
+            let _ = client.stream.lock().shutdown(Shutdown::Both);
+        }
+
+        Ok(())
+    }
+
+    fn accept(&mut self, listener: usize) {
+        let listener = &self.listeners[listener];
+
+        loop {
+            match listener.accept() {
+                Ok((stream, peer)) => {
+                    let token = Token(self.next_token);
+                    self.next_token += 1;
+
+                    handler::server::handle_incoming_client(
+                        &self.state,
+                        self.poll.registry(),
+                        listener,
+                        token,
+                        stream,
+                        peer,
+                    );
+                }
+
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+
+                Err(e) => {
+                    log::warn!("[Listener: {}] Failed to accept client: {}", listener, e);
+                    break;
+                }
+            }
+        }
+    }
+
+    fn handle_readiness(&mut self, event: &ReadinessEvent) {
+        let client = match self.state.get_client_by_token(event.token()) {
+            Some(client) => client,
+            None => return,
+        };
+
+        if event.is_readable() || event.is_read_closed() || event.is_error() {
+            handler::client::handle_readable(&self.state, &client, &self.event_tx);
+        }
+
+        if event.is_writable() {
+            handler::client::handle_writable(&client);
+        }
+    }
+
+    /// Handles queued events and drops every client that became inactive meanwhile.
+    fn process_events(&mut self) {
+        loop {
+            for event in self.event_rx.try_iter() {
+                handler::server::handle_event(&self.state, event, &self.event_tx);
+            }
+
+            let inactive = self
+                .state
+                .clients
+                .lock()
+                .iter()
+                .filter(|client| !*client.active.read())
+                .cloned()
+                .collect::<Vec<_>>();
+
+            if inactive.is_empty() {
+                break;
+            }
+
+            for client in inactive {
+                let _ = client.flush();
+                let _ = self.poll.registry().deregister(&mut *client.stream.lock());
+
+                handler::server::handle_event(&self.state, Event::Dropped(client), &self.event_tx);
+            }
+        }
+    }
+
+    /// Time until the next timer is due.
+    fn timeout(&self) -> Duration {
+        let housekeeping = HOUSEKEEPING_INTERVAL.saturating_sub(self.last_housekeeping.elapsed());
+
+        match self.state.config().keepalive_interval() {
+            Some(interval) => {
+                housekeeping.min(interval.saturating_sub(self.last_keepalive.elapsed()))
+            }
+            None => housekeeping,
+        }
+    }
+
+    /// Pings clients that have been silent for a while and drops those that timed out.
+    fn keepalive(&mut self, interval: Duration) {
+        self.last_keepalive = Instant::now();
+
+        let token = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_millis() as u64)
+            .unwrap_or_default();
+
+        for client in self.state.clients.lock().iter() {
+            let silence = client.last_seen.read().elapsed();
+
+            if silence >= self.state.config().client_timeout() {
+                log::info!("[Client: {}] Timed out after {:?}.", client, silence);
+
+                *client.active.write() = false;
+            } else if silence >= interval {
+                let _ = client.send(ServerMessage::Ping { token });
+            }
+        }
+    }
+
+    fn housekeeping(&mut self) {
+        self.last_housekeeping = Instant::now();
+
+        let throttled = self.state.metrics.throttled.load(Ordering::Relaxed);
+
+        if throttled != self.reported_throttled {
+            self.reported_throttled = throttled;
+
+            log::info!("Rate limiting: {}", self.state.metrics);
+        }
+
+        if let Some(cutoff) = history::retention_cutoff(&self.state.config()) {
+            if let Err(e) = self.state.history.prune(cutoff) {
+                log::warn!("Failed to expire history: {}", e);
+            }
+
+            let history = self.state.history.clone();
+
+            self.state.worker.run(move || {
+                if let Err(e) = history.compact() {
+                    log::warn!("Failed to compact history: {}", e);
+                }
+            });
+        }
+
+        let cutoff = chrono::Utc::now()
+            - chrono::Duration::from_std(self.state.config().session_grace())
+                .unwrap_or_else(|_| chrono::Duration::zero());
+
+        for session in self.state.expire_sessions(cutoff) {
+            log::info!("Session of {} expired", session.nick);
+
+            let leave = ServerMessage::Refer {
+                user: session.nick,
+                message: UserMessage::Leave {
+                    message: Some("Connection lost".to_owned()),
+                },
+            };
+
+            for client in self.state.clients.lock().iter() {
+                let _ = client.send(leave.clone());
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use chrono::{DateTime, Utc};
+use mio::Token;
+use rand::{distributions::Alphanumeric, Rng};
+
+use lvchat_core::Message;
+
+/// Length of generated session tokens.
+const TOKEN_LEN: usize = 32;
+
+/// Most messages kept for a detached session.
+pub const MAX_MISSED: usize = 100;
+
+/// Authenticated identity that outlives its connection for a grace period.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub token: String,
+    pub nick: String,
+
+    /// Client currently attached, `None` while detached.
+    pub client: Option<Token>,
+
+    /// Channels to rejoin on resumption.
+    pub channels: Vec<String>,
+    pub detached_at: Option<DateTime<Utc>>,
+
+    /// Private messages received while detached.
+    pub missed: Vec<Message>,
+
+    /// Set while muted, `None` inside for a mute without end.
+    pub muted: Option<Option<DateTime<Utc>>>,
+}
+
+impl Session {
+    pub fn new<S: Into<String>>(nick: S, client: Token) -> Self {
+        let token = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(TOKEN_LEN)
+            .map(char::from)
+            .collect();
+
+        Session {
+            token,
+            nick: nick.into(),
+            client: Some(client),
+            channels: vec![],
+            detached_at: None,
+            missed: vec![],
+            muted: None,
+        }
+    }
+
+    pub fn is_detached(&self) -> bool {
+        self.client.is_none()
+    }
+}
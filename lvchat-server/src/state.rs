@@ -1,36 +1,249 @@
 use std::{
-    net::SocketAddr,
+    collections::HashMap,
+    io,
+    net::IpAddr,
     sync::Arc,
+    time::{Duration, Instant},
 };
 
-use parking_lot::Mutex;
+use chrono::{DateTime, Utc};
 
-use crate::{client::Client, config::Config};
+use mio::Token;
+use parking_lot::{Mutex, RwLock};
+
+use lvchat_core::Message;
+
+use crate::{
+    accounts::Accounts,
+    bans::Bans,
+    channel::Channel,
+    client::Client,
+    config::Config,
+    history::{self, HistoryStore},
+    limiter::Metrics,
+    motd,
+    session::{Session, MAX_MISSED},
+    tls,
+    worker::Worker,
+};
+
+/// Failed operator logins from an address after which it is locked out.
+pub const MAX_OPER_FAILURES: u32 = 3;
+
+/// How long an address is locked out of operator logins after its last failure.
+pub const OPER_LOCKOUT: Duration = Duration::from_secs(600);
+
+/// Count and time of the last failed operator login by address.
+type OperFailures = HashMap<Option<IpAddr>, (u32, Instant)>;
 
 #[derive(Debug, Clone)]
 pub struct State {
-    pub config: Arc<Config>,
+    /// Replaced as a whole when reloaded on SIGHUP.
+    pub config: Arc<RwLock<Arc<Config>>>,
     pub clients: Arc<Mutex<Vec<Client>>>,
+    pub channels: Arc<Mutex<HashMap<String, Channel>>>,
+    pub history: Arc<dyn HistoryStore>,
+    pub accounts: Arc<Accounts>,
+    pub bans: Arc<Bans>,
+
+    /// Mutes of registered nicks, until the given time or until the server restarts.
+    ///
+    /// Taken on by every session of the account, while mutes of guests end with their session.
+    pub mutes: Arc<Mutex<HashMap<String, Option<DateTime<Utc>>>>>,
+
+    /// Failed operator logins by address, `None` for local clients, with the time of the last.
+    pub oper_failures: Arc<Mutex<OperFailures>>,
+
+    /// Sessions by token.
+    pub sessions: Arc<Mutex<HashMap<String, Session>>>,
+
+    /// Set if clients connect over TLS.
+    pub tls: Option<Arc<rustls::ServerConfig>>,
+
+    /// Counters of rate limited traffic.
+    pub metrics: Arc<Metrics>,
+
+    /// Template of the message of the day.
+    pub motd: Arc<RwLock<Option<String>>>,
+    pub started: Instant,
+
+    /// Hashes passwords and compacts history off the reactor thread.
+    pub worker: Worker,
 }
 
 impl State {
-    pub fn new(config: Config) -> Self {
-        State {
-            config: Arc::new(config),
+    pub fn new(config: Config) -> io::Result<Self> {
+        let history = history::open(&config)?;
+        let accounts = Accounts::open(&config)?;
+        let bans = Bans::open(&config)?;
+        let motd = motd::load(&config)?;
+        let tls = tls::load(&config)?;
+
+        Ok(State {
+            config: Arc::new(RwLock::new(Arc::new(config))),
             clients: Arc::new(Mutex::new(vec![])),
+            channels: Arc::new(Mutex::new(HashMap::new())),
+            history,
+            accounts: Arc::new(accounts),
+            bans: Arc::new(bans),
+            mutes: Arc::new(Mutex::new(HashMap::new())),
+            oper_failures: Arc::new(Mutex::new(HashMap::new())),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            tls,
+            metrics: Arc::new(Metrics::default()),
+            motd: Arc::new(RwLock::new(motd)),
+            started: Instant::now(),
+            worker: Worker::spawn("worker")?,
+        })
+    }
+}
+
+impl State {
+    /// Returns when the mute of the client ends, if it is muted. `None` inside for a mute without
+    /// end.
+    pub fn muted_until(&self, client: &Client) -> Option<Option<DateTime<Utc>>> {
+        let token = client.session.read().clone()?;
+        let mut sessions = self.sessions.lock();
+        let session = sessions.get_mut(&token)?;
+
+        match session.muted {
+            Some(Some(until)) if until <= Utc::now() => {
+                session.muted = None;
+                None
+            }
+
+            muted => muted,
+        }
+    }
+
+    /// Mutes whoever holds `nick`, and its account if registered, until the given time.
+    pub fn mute(&self, nick: &str, until: Option<DateTime<Utc>>) {
+        self.set_muted(nick, Some(until));
+    }
+
+    pub fn unmute(&self, nick: &str) {
+        self.set_muted(nick, None);
+    }
+
+    fn set_muted(&self, nick: &str, muted: Option<Option<DateTime<Utc>>>) {
+        if self.accounts.is_registered(nick) {
+            let mut mutes = self.mutes.lock();
+
+            match muted {
+                Some(until) => mutes.insert(nick.to_owned(), until),
+                None => mutes.remove(nick),
+            };
+        }
+
+        if let Some(session) = self
+            .sessions
+            .lock()
+            .values_mut()
+            .find(|session| session.nick == nick)
+        {
+            session.muted = muted;
+        }
+    }
+
+    /// Mute of the account registered as `nick`, if any.
+    fn account_muted_until(&self, nick: &str) -> Option<Option<DateTime<Utc>>> {
+        let mut mutes = self.mutes.lock();
+
+        match mutes.get(nick) {
+            Some(Some(until)) if *until <= Utc::now() => {
+                mutes.remove(nick);
+                None
+            }
+
+            until => until.cloned(),
         }
     }
 }
 
 impl State {
-    pub fn get_client_by_addr(&self, addr: &SocketAddr) -> Option<Client> {
-        for client in self.clients.lock().iter() {
-            if client.user.read().addr().ip() == addr.ip() {
-                return Some(client.clone());
+    /// Whether `ip` may try the operator password, which it may not for `OPER_LOCKOUT` after
+    /// failing `MAX_OPER_FAILURES` times.
+    pub fn may_oper(&self, ip: Option<IpAddr>) -> bool {
+        let mut failures = self.oper_failures.lock();
+
+        match failures.get(&ip) {
+            Some((_, last)) if last.elapsed() >= OPER_LOCKOUT => {
+                failures.remove(&ip);
+                true
             }
+
+            Some((count, _)) => *count < MAX_OPER_FAILURES,
+            None => true,
         }
+    }
 
-        None
+    /// Counts a failed operator login from `ip`.
+    pub fn oper_failed(&self, ip: Option<IpAddr>) {
+        let mut failures = self.oper_failures.lock();
+        let failure = failures.entry(ip).or_insert((0, Instant::now()));
+
+        *failure = (failure.0 + 1, Instant::now());
+    }
+}
+
+impl State {
+    /// Current configuration. Hold on to it to read several settings consistently.
+    pub fn config(&self) -> Arc<Config> {
+        self.config.read().clone()
+    }
+
+    /// Reads the configuration file again, keeping the current configuration if it's invalid.
+    pub fn reload_config(&self) {
+        let current = self.config();
+
+        let mut config = match current.reload() {
+            Ok(config) => config,
+
+            Err(e) => {
+                log::error!("Failed to reload configuration: {}", e);
+                return;
+            }
+        };
+
+        for setting in config.keep_restart_settings(&current) {
+            log::warn!("Changing {} takes effect after a restart", setting);
+        }
+
+        *self.config.write() = Arc::new(config);
+
+        log::info!("Reloaded configuration");
+    }
+
+    /// Re-reads the message of the day, keeping the current one if that fails.
+    pub fn reload_motd(&self) {
+        match motd::load(&self.config()) {
+            Ok(motd) => *self.motd.write() = motd,
+            Err(e) => log::warn!("Failed to reload message of the day: {}", e),
+        }
+    }
+
+    /// Message of the day as it should be shown right now.
+    pub fn render_motd(&self) -> Option<String> {
+        let users = self
+            .clients
+            .lock()
+            .iter()
+            .filter(|client| !client.user.read().is_ghost())
+            .count();
+
+        self.motd.read().as_ref().map(|template| {
+            motd::render(template, &self.config().name, users, self.started.elapsed())
+        })
+    }
+}
+
+impl State {
+    pub fn get_client_by_token(&self, token: Token) -> Option<Client> {
+        self.clients
+            .lock()
+            .iter()
+            .find(|client| client.token == token)
+            .cloned()
     }
 
     pub fn get_client_by_name(&self, name: &str) -> Option<Client> {
@@ -42,4 +255,210 @@ impl State {
 
         None
     }
+
+    /// Whether the nick belongs to a connected client or a detached session.
+    pub fn is_nick_taken(&self, name: &str) -> bool {
+        self.get_client_by_name(name).is_some() || self.get_session_by_nick(name).is_some()
+    }
+}
+
+impl State {
+    pub fn get_channel(&self, name: &str) -> Option<Channel> {
+        self.channels.lock().get(name).cloned()
+    }
+
+    /// Adds the client to the channel, creating it if needed.
+    ///
+    /// Returns `false` if the client already was a member.
+    pub fn join_channel(&self, name: &str, client: &Client) -> bool {
+        let mut channels = self.channels.lock();
+        let channel = channels
+            .entry(name.to_owned())
+            .or_insert_with(|| Channel::new(name));
+
+        if channel.is_member(client) {
+            return false;
+        }
+
+        channel.members.push(client.clone());
+
+        true
+    }
+
+    /// Removes the client from the channel, dropping the channel once it's empty.
+    ///
+    /// Returns `false` if the client wasn't a member.
+    pub fn part_channel(&self, name: &str, client: &Client) -> bool {
+        let mut channels = self.channels.lock();
+
+        let parted = match channels.get_mut(name) {
+            Some(channel) => match channel.members.iter().position(|member| member == client) {
+                Some(pos) => {
+                    channel.members.remove(pos);
+
+                    true
+                }
+
+                None => false,
+            },
+
+            None => false,
+        };
+
+        channels.retain(|_, channel| !channel.members.is_empty());
+
+        parted
+    }
+
+    /// Removes the client from every channel it joined and returns their names.
+    pub fn part_all_channels(&self, client: &Client) -> Vec<String> {
+        let mut channels = self.channels.lock();
+        let mut parted = vec![];
+
+        for channel in channels.values_mut() {
+            if let Some(pos) = channel.members.iter().position(|member| member == client) {
+                channel.members.remove(pos);
+                parted.push(channel.name.clone());
+            }
+        }
+
+        channels.retain(|_, channel| !channel.members.is_empty());
+
+        parted
+    }
+}
+
+impl State {
+    pub fn get_session(&self, token: &str) -> Option<Session> {
+        self.sessions.lock().get(token).cloned()
+    }
+
+    pub fn get_session_by_nick(&self, nick: &str) -> Option<Session> {
+        self.sessions
+            .lock()
+            .values()
+            .find(|session| session.nick == nick)
+            .cloned()
+    }
+
+    /// Starts a session for a freshly authenticated client and returns its token.
+    pub fn create_session(&self, client: &Client) -> String {
+        let nick = client.user.read().nick_unchecked().to_owned();
+        let mut session = Session::new(nick.as_str(), client.token);
+        let token = session.token.clone();
+
+        session.muted = self.account_muted_until(&nick);
+
+        self.sessions.lock().insert(token.clone(), session);
+        *client.session.write() = Some(token.clone());
+
+        token
+    }
+
+    /// Attaches the client to the session, returning the session as it was before.
+    pub fn attach_session(&self, token: &str, client: &Client) -> Option<Session> {
+        let mut sessions = self.sessions.lock();
+        let session = sessions.get_mut(token)?;
+        let previous = session.clone();
+
+        session.client = Some(client.token);
+        session.channels.clear();
+        session.detached_at = None;
+        session.missed.clear();
+
+        *client.session.write() = Some(token.to_owned());
+
+        Some(previous)
+    }
+
+    /// Keeps the session around for resumption after its client disconnected.
+    pub fn detach_session(&self, token: &str, channels: Vec<String>) {
+        if let Some(session) = self.sessions.lock().get_mut(token) {
+            session.client = None;
+            session.channels = channels;
+            session.detached_at = Some(Utc::now());
+        }
+    }
+
+    /// Keeps a message for the detached session of `nick`.
+    ///
+    /// Returns `false` if there is no such session.
+    pub fn queue_missed(&self, nick: &str, message: Message) -> bool {
+        let mut sessions = self.sessions.lock();
+
+        let session = match sessions
+            .values_mut()
+            .find(|session| session.is_detached() && session.nick == nick)
+        {
+            Some(session) => session,
+            None => return false,
+        };
+
+        if session.missed.len() >= MAX_MISSED {
+            session.missed.remove(0);
+        }
+
+        session.missed.push(message);
+
+        true
+    }
+
+    pub fn end_session(&self, token: &str) {
+        self.sessions.lock().remove(token);
+    }
+
+    /// Renames the session, which takes on the mute of the account if `nick` is registered.
+    ///
+    /// A mute of the session itself is carried along.
+    pub fn rename_session(&self, token: &str, nick: &str) {
+        let account_muted = self.account_muted_until(nick);
+
+        if let Some(session) = self.sessions.lock().get_mut(token) {
+            session.nick = nick.to_owned();
+
+            if account_muted.is_some() {
+                session.muted = account_muted;
+            }
+        }
+    }
+
+    /// Removes and returns every session detached before `cutoff`.
+    pub fn expire_sessions(&self, cutoff: DateTime<Utc>) -> Vec<Session> {
+        let mut sessions = self.sessions.lock();
+
+        let expired = sessions
+            .values()
+            .filter(|session| session.detached_at.is_some_and(|at| at < cutoff))
+            .map(|session| session.token.clone())
+            .collect::<Vec<_>>();
+
+        expired
+            .iter()
+            .filter_map(|token| sessions.remove(token))
+            .collect()
+    }
+}
+
+#[test]
+fn lock_out_failed_operators() {
+    use structopt::StructOpt;
+
+    use crate::config::Args;
+
+    let args = Args::from_iter(&["lvchat-server", "--quiet"]);
+    let state = State::new(Config::load(args).unwrap()).unwrap();
+    let ip = Some(IpAddr::from([10, 0, 0, 1]));
+
+    for _ in 0..MAX_OPER_FAILURES {
+        assert!(state.may_oper(ip));
+        state.oper_failed(ip);
+    }
+
+    assert!(!state.may_oper(ip));
+    assert!(state.may_oper(Some(IpAddr::from([10, 0, 0, 2]))));
+    assert!(state.may_oper(None));
+
+    state.oper_failures.lock().get_mut(&ip).unwrap().1 = Instant::now() - OPER_LOCKOUT;
+
+    assert!(state.may_oper(ip));
 }
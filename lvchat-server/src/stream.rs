@@ -0,0 +1,101 @@
+use std::{
+    io::{self, Read, Write},
+    net::Shutdown,
+};
+
+#[cfg(unix)]
+use mio::net::UnixStream;
+use mio::{event::Source, net::TcpStream, Interest, Registry, Token};
+
+use crate::tls::TlsStream;
+
+/// Connection to a client, encrypted if it came through a TLS listener.
+#[derive(Debug)]
+pub enum Stream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream>),
+
+    /// Local client connected through a Unix domain socket.
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    pub fn shutdown(&mut self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Stream::Plain(sock) => sock.shutdown(how),
+
+            Stream::Tls(tls) => {
+                tls.close();
+                tls.sock.shutdown(how)
+            }
+
+            #[cfg(unix)]
+            Stream::Unix(sock) => sock.shutdown(how),
+        }
+    }
+
+    fn source(&mut self) -> &mut dyn Source {
+        match self {
+            Stream::Plain(sock) => sock,
+            Stream::Tls(tls) => &mut tls.sock,
+            #[cfg(unix)]
+            Stream::Unix(sock) => sock,
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(sock) => sock.read(buf),
+            Stream::Tls(tls) => tls.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(sock) => sock.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(sock) => sock.write(buf),
+            Stream::Tls(tls) => tls.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(sock) => sock.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(sock) => sock.flush(),
+            Stream::Tls(tls) => tls.flush(),
+            #[cfg(unix)]
+            Stream::Unix(sock) => sock.flush(),
+        }
+    }
+}
+
+impl Source for Stream {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        self.source().register(registry, token, interests)
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        self.source().reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        self.source().deregister(registry)
+    }
+}
@@ -0,0 +1,188 @@
+//! TLS transport, enabled by passing `--cert` and `--key`.
+
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use mio::net::TcpStream;
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    ServerConfig, ServerConnection,
+};
+
+use crate::config::Config;
+
+/// Builds the TLS configuration, `None` if the server runs in plaintext.
+pub fn load(config: &Config) -> io::Result<Option<Arc<ServerConfig>>> {
+    match (&config.cert_path, &config.key_path) {
+        (Some(cert), Some(key)) => server_config(cert, key).map(Some),
+
+        (None, None) => Ok(None),
+
+        _ => Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "--cert and --key must be given together",
+        )),
+    }
+}
+
+/// Reads a PEM encoded certificate chain and private key.
+pub fn server_config<P: AsRef<Path>>(cert: P, key: P) -> io::Result<Arc<ServerConfig>> {
+    let certs = CertificateDer::pem_file_iter(cert.as_ref())
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid_data(cert.as_ref(), e))?;
+    let key =
+        PrivateKeyDer::from_pem_file(key.as_ref()).map_err(|e| invalid_data(key.as_ref(), e))?;
+
+    let config =
+        ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(io::Error::other)?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+
+    Ok(Arc::new(config))
+}
+
+fn invalid_data<E: std::fmt::Display>(path: &Path, e: E) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+}
+
+/// Server side of a TLS session over a non-blocking socket.
+///
+/// Reads and writes return `WouldBlock` whenever the socket does, like a plain `TcpStream`.
+/// Records that couldn't be written yet stay buffered until the next `flush`.
+#[derive(Debug)]
+pub struct TlsStream {
+    pub sock: TcpStream,
+    pub conn: ServerConnection,
+}
+
+impl TlsStream {
+    pub fn new(sock: TcpStream, config: Arc<ServerConfig>) -> io::Result<Self> {
+        let conn = ServerConnection::new(config).map_err(io::Error::other)?;
+
+        Ok(TlsStream { sock, conn })
+    }
+
+    /// Writes buffered records until the socket would block.
+    fn write_records(&mut self) -> io::Result<()> {
+        while self.conn.wants_write() {
+            self.conn.write_tls(&mut self.sock)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends `close_notify`, so the peer can tell a clean close from a truncation.
+    pub fn close(&mut self) {
+        self.conn.send_close_notify();
+
+        let _ = self.write_records();
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.conn.reader().read(buf) {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => (),
+                result => return result,
+            }
+
+            if self.conn.read_tls(&mut self.sock)? == 0 {
+                return Ok(0);
+            }
+
+            let processed = self.conn.process_new_packets();
+
+            // handshake replies and alerts go out right away
+            match self.write_records() {
+                Err(e) if e.kind() != ErrorKind::WouldBlock => return Err(e),
+                _ => (),
+            }
+
+            processed.map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_records()?;
+
+        let size = self.conn.writer().write(buf)?;
+
+        if size == 0 && !buf.is_empty() {
+            return Err(ErrorKind::WouldBlock.into());
+        }
+
+        match self.write_records() {
+            Err(e) if e.kind() != ErrorKind::WouldBlock => Err(e),
+            _ => Ok(size),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_records()
+    }
+}
+
+#[test]
+fn handshake_with_self_signed_certificate() {
+    use std::{convert::TryFrom, net::SocketAddr, thread::spawn};
+
+    use lvchat_core::{Hello, Message, ServerMessage, UserMessage};
+    use rustls::{pki_types::ServerName, ClientConfig, ClientConnection, RootCertStore};
+    use structopt::StructOpt;
+
+    use crate::{config::Args, reactor::Reactor, state::State};
+
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let cert = dir.path().join("cert.pem");
+    let key = dir.path().join("key.pem");
+
+    std::fs::write(&cert, certified.cert.pem()).unwrap();
+    std::fs::write(&key, certified.key_pair.serialize_pem()).unwrap();
+
+    let args = Args::from_iter(&[
+        "lvchat-server",
+        "--quiet",
+        "--port",
+        "0",
+        "--cert",
+        cert.to_str().unwrap(),
+        "--key",
+        key.to_str().unwrap(),
+    ]);
+    let config = Config::load(args).unwrap();
+
+    let reactor = Reactor::bind(State::new(config).unwrap()).unwrap();
+    let addr = SocketAddr::from(([127, 0, 0, 1], reactor.local_addrs().unwrap()[0].port()));
+
+    spawn(move || reactor.run());
+
+    let mut roots = RootCertStore::empty();
+    roots.add(certified.cert.der().clone()).unwrap();
+
+    let tls =
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+    let conn =
+        ClientConnection::new(Arc::new(tls), ServerName::try_from("localhost").unwrap()).unwrap();
+    let mut stream = rustls::StreamOwned::new(conn, std::net::TcpStream::connect(addr).unwrap());
+
+    Message::send(&mut stream, UserMessage::Hello(Hello::new("test"))).unwrap();
+
+    assert!(matches!(
+        Message::recv(&mut stream),
+        Ok(Message::Server(ServerMessage::Hello(_)))
+    ));
+}
@@ -0,0 +1,41 @@
+//! Thread for work too slow for the reactor, like hashing passwords.
+
+use std::{fmt, io, thread};
+
+use flume::Sender;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Runs jobs one after another on a thread of its own, which ends once every handle is dropped.
+#[derive(Clone)]
+pub struct Worker {
+    jobs: Sender<Job>,
+}
+
+impl Worker {
+    pub fn spawn(name: &str) -> io::Result<Self> {
+        let (jobs, queue) = flume::unbounded::<Job>();
+
+        thread::Builder::new()
+            .name(name.to_owned())
+            .spawn(move || {
+                for job in queue.iter() {
+                    job();
+                }
+            })?;
+
+        Ok(Worker { jobs })
+    }
+
+    pub fn run<F: FnOnce() + Send + 'static>(&self, job: F) {
+        if self.jobs.send(Box::new(job)).is_err() {
+            log::warn!("Worker is gone, dropping job");
+        }
+    }
+}
+
+impl fmt::Debug for Worker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Worker").finish_non_exhaustive()
+    }
+}